@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use crate::EmojiDetails;
+
+// emojis.cache holds the parsed emojis file in a binary form that loads faster than the JSON. It
+// starts with the size and modification time of the file it was made from and is only used while
+// those still match. Strings are a little-endian u32 byte length followed by UTF-8, and each map
+// (emojis, groups, keywords, descriptions, fallbacks, aliases) is a u32 count followed by its key/value pairs.
+
+const MAGIC: &[u8] = b"RUSTOJI4";
+
+type Emojis = HashMap<String, String>;
+
+// None means there's no usable cache and the emojis file has to be parsed.
+pub fn load(cache_path: &Path, source_path: &Path) -> Option<(Emojis, EmojiDetails)> {
+    let bytes = fs::read(cache_path).ok()?;
+    let mut reader = Reader { bytes: &bytes };
+    if reader.take(MAGIC.len())? != MAGIC || reader.take(20)? != stamp(source_path)? {
+        return None;
+    }
+    let unicode_emojis = reader.map()?;
+    let details = EmojiDetails {
+        groups: reader.map()?,
+        keywords: reader.map()?,
+        descriptions: reader.map()?,
+        fallbacks: reader.map()?,
+        aliases: reader.map()?,
+    };
+    reader.bytes.is_empty().then_some((unicode_emojis, details))
+}
+
+pub fn save(
+    cache_path: &Path,
+    source_path: &Path,
+    unicode_emojis: &Emojis,
+    details: &EmojiDetails,
+) -> io::Result<()> {
+    let stamp = stamp(source_path).ok_or_else(|| {
+        io::Error::other(format!("Can't read the mtime of {}", source_path.display()))
+    })?;
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend(stamp);
+    for map in [
+        unicode_emojis,
+        &details.groups,
+        &details.keywords,
+        &details.descriptions,
+        &details.fallbacks,
+        &details.aliases,
+    ] {
+        write_map(&mut bytes, map);
+    }
+
+    // like history.json, written next to the old one and renamed over it
+    let temp_path = cache_path.with_extension("cache.tmp");
+    fs::write(&temp_path, bytes)?;
+    fs::rename(temp_path, cache_path)
+}
+
+fn stamp(path: &Path) -> Option<Vec<u8>> {
+    let metadata = fs::metadata(path).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    let mut stamp = Vec::with_capacity(20);
+    stamp.extend(metadata.len().to_le_bytes());
+    stamp.extend(modified.as_secs().to_le_bytes());
+    stamp.extend(modified.subsec_nanos().to_le_bytes());
+    Some(stamp)
+}
+
+fn write_map(bytes: &mut Vec<u8>, map: &Emojis) {
+    bytes.extend((map.len() as u32).to_le_bytes());
+    for (key, value) in map {
+        for string in [key, value] {
+            bytes.extend((string.len() as u32).to_le_bytes());
+            bytes.extend(string.as_bytes());
+        }
+    }
+}
+
+// Every read returns None once the data runs out, so a truncated cache is just not used.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len > self.bytes.len() {
+            return None;
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Some(taken)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).ok()
+    }
+
+    fn map(&mut self) -> Option<Emojis> {
+        let count = self.u32()?;
+        (0..count)
+            .map(|_| Some((self.string()?, self.string()?)))
+            .collect()
+    }
+}
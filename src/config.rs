@@ -0,0 +1,513 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// A small reader for the subset of TOML the config file needs: `[table]` headers and single-line
+// `key = value` pairs whose values are strings, integers, floats, booleans or arrays of those.
+// Keys are bare or quoted.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::String(value) => write!(f, "{value:?}"),
+            Value::Integer(value) => write!(f, "{value}"),
+            Value::Float(value) => write!(f, "{value}"),
+            Value::Boolean(value) => write!(f, "{value}"),
+            Value::Array(values) => {
+                let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+                write!(f, "[{}]", values.join(", "))
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Config {
+    values: HashMap<String, Value>, // keyed by the full dotted path, e.g. "picker.default"
+}
+
+impl Config {
+    // A missing file is an empty config.
+    pub fn load(path: &Path) -> io::Result<Config> {
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        let text = fs::read_to_string(path)?;
+        Config::parse(&text).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {err}", path.display()),
+            )
+        })
+    }
+
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut values = HashMap::new();
+        let mut table = String::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let at_line = |msg: &str| format!("line {}: {msg}", number + 1);
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(header) = line.strip_prefix('[') {
+                let name = header
+                    .strip_suffix(']')
+                    .ok_or_else(|| at_line("unterminated table header"))?
+                    .trim();
+                if !is_valid_key(name) {
+                    return Err(at_line(&format!("invalid table name `{name}`")));
+                }
+                table = name.to_string();
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| at_line("expected `key = value`"))?;
+            let key = key.trim();
+            // a quoted key may hold any characters, e.g. a group name like "Smileys & Emotion"
+            let key = match parse_value(key) {
+                _ if is_valid_key(key) => key.to_string(),
+                Ok((Value::String(quoted), rest))
+                    if key.starts_with(['"', '\'']) && !quoted.is_empty() && rest.is_empty() =>
+                {
+                    quoted
+                }
+                _ => return Err(at_line(&format!("invalid key `{key}`"))),
+            };
+            let full_key = if table.is_empty() {
+                key.to_string()
+            } else {
+                format!("{table}.{key}")
+            };
+
+            let (value, rest) = parse_value(value.trim()).map_err(|err| at_line(&err))?;
+            if !rest.trim().is_empty() {
+                return Err(at_line("unexpected characters after the value"));
+            }
+            if values.insert(full_key, value).is_some() {
+                return Err(at_line(&format!("duplicate key `{key}`")));
+            }
+        }
+
+        Ok(Config { values })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.values.get(key)
+    }
+
+    pub fn string(&self, key: &str) -> io::Result<Option<String>> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(Value::String(value)) => Ok(Some(value.clone())),
+            Some(other) => Err(type_error(key, "a string", other)),
+        }
+    }
+
+    // Negative numbers are rejected, every integer option is a count.
+    pub fn integer(&self, key: &str) -> io::Result<Option<u64>> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(Value::Integer(value)) if *value >= 0 => Ok(Some(*value as u64)),
+            Some(other) => Err(type_error(key, "a non-negative integer", other)),
+        }
+    }
+
+    // An integer is accepted too, so `png_weight = 2` works like `2.0`.
+    pub fn float(&self, key: &str) -> io::Result<Option<f64>> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(Value::Float(value)) if *value >= 0.0 => Ok(Some(*value)),
+            Some(Value::Integer(value)) if *value >= 0 => Ok(Some(*value as f64)),
+            Some(other) => Err(type_error(key, "a non-negative number", other)),
+        }
+    }
+
+    pub fn strings(&self, key: &str) -> io::Result<Option<Vec<String>>> {
+        let Some(value) = self.get(key) else {
+            return Ok(None);
+        };
+        let strings = match value {
+            Value::Array(values) => values
+                .iter()
+                .map(|value| match value {
+                    Value::String(value) => Some(value.clone()),
+                    _ => None,
+                })
+                .collect(),
+            _ => None,
+        };
+        strings
+            .map(Some)
+            .ok_or_else(|| type_error(key, "an array of strings", value))
+    }
+
+    // The string values of a table's keys, e.g. `[picker.env]`, sorted by key.
+    pub fn string_table(&self, table: &str) -> io::Result<Vec<(String, String)>> {
+        let prefix = format!("{table}.");
+        let mut entries = Vec::new();
+        for (key, value) in &self.values {
+            let Some(name) = key.strip_prefix(&prefix) else {
+                continue;
+            };
+            match value {
+                Value::String(value) => entries.push((name.to_string(), value.clone())),
+                other => return Err(type_error(key, "a string", other)),
+            }
+        }
+        entries.sort();
+        Ok(entries)
+    }
+
+    pub fn boolean(&self, key: &str) -> io::Result<Option<bool>> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(Value::Boolean(value)) => Ok(Some(*value)),
+            Some(other) => Err(type_error(key, "a boolean", other)),
+        }
+    }
+}
+
+fn type_error(key: &str, expected: &str, found: &Value) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("config key `{key}` must be {expected}, found {found}"),
+    )
+}
+
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && key.split('.').all(|part| {
+            !part.is_empty()
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        })
+}
+
+// Drops a `#` comment, ignoring any `#` inside a quoted string.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        match quote {
+            Some('"') if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '#' => return &line[..index],
+            None => {}
+        }
+    }
+    line
+}
+
+// Parses one value from the start of `input`, returning it with the unparsed remainder.
+fn parse_value(input: &str) -> Result<(Value, &str), String> {
+    if let Some(rest) = input.strip_prefix('"') {
+        let mut value = String::new();
+        let mut chars = rest.char_indices();
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '"' => return Ok((Value::String(value), &rest[index + 1..])),
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, 't')) => value.push('\t'),
+                    Some((_, '"')) => value.push('"'),
+                    Some((_, '\\')) => value.push('\\'),
+                    _ => return Err("unsupported escape sequence".to_string()),
+                },
+                _ => value.push(c),
+            }
+        }
+        return Err("unterminated string".to_string());
+    }
+
+    if let Some(rest) = input.strip_prefix('\'') {
+        let end = rest.find('\'').ok_or("unterminated string")?;
+        return Ok((Value::String(rest[..end].to_string()), &rest[end + 1..]));
+    }
+
+    if let Some(mut rest) = input.strip_prefix('[') {
+        let mut values = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if let Some(after) = rest.strip_prefix(']') {
+                return Ok((Value::Array(values), after));
+            }
+            let (value, after) = parse_value(rest)?;
+            values.push(value);
+            rest = after.trim_start();
+            if let Some(after) = rest.strip_prefix(',') {
+                rest = after;
+            } else if !rest.starts_with(']') {
+                return Err("expected `,` or `]` in array".to_string());
+            }
+        }
+    }
+
+    let end = input
+        .find(|c: char| c == ',' || c == ']' || c.is_whitespace())
+        .unwrap_or(input.len());
+    let (token, rest) = input.split_at(end);
+    let value = match token {
+        "true" => Value::Boolean(true),
+        "false" => Value::Boolean(false),
+        _ => {
+            if let Ok(value) = token.parse() {
+                Value::Integer(value)
+            } else if let Ok(value) = token.parse() {
+                Value::Float(value)
+            } else {
+                return Err(format!("invalid value `{token}`"));
+            }
+        }
+    };
+    Ok((value, rest))
+}
+
+// Written by `rustoji init`. Every option is commented out and shows its default.
+pub const DEFAULT_CONFIG: &str = r#"# rustoji configuration
+# Command line arguments take precedence over the values in this file.
+
+# Where emojis.json, history.json and the other data files live, and where png emojis are
+# looked for. The config file itself can be moved with --config.
+# data_dir = "~/.local/share/rustoji"
+# png_dir = "~/assets/emojis"
+
+# Names of the emojis and history files in the data folder, e.g. to keep two setups' histories
+# apart in one data folder. A missing emojis file is downloaded under its name.
+# emojis_file_name = "emojis.json"
+# history_file_name = "history.json"
+
+# Profile whose emojis.json, history and other data files are used, kept in
+# <data_dir>/profiles/<name>. Unset uses the data folder itself. --profile picks one per run.
+# profile = "work"
+
+# How png emojis are copied: "path" copies their file:// path, "image" the image itself.
+# RUSTOJI_COPY_MODE and --copy override it. Replaces copy_png_path, which is still read.
+# copy_mode = "path"
+
+# Copy png emojis as images scaled to fit this many pixels, e.g. for even sizes in chats. Needs
+# ImageMagick; the scaled copies are kept under resized/ in the data folder. Unset copies the
+# original file.
+# image_size = 128
+
+# Append a newline to copied text emojis
+# newline = false
+
+# More emojis files, in either emojis.json format, merged over the downloaded one in order. An
+# emoji in a later file replaces one with the same name. --emojis adds to this list.
+# emojis = ["~/.config/rustoji/overrides.json"]
+
+# Copy the emoji followed by its name, e.g. "😀 grinning". Png emojis copied as an image or a
+# path can't carry the name, so it's only added to their html.
+# with_name = false
+
+# Copy the emoji followed by its ascii fallback, e.g. "😀 :D", for emojis files whose entries
+# carry an "ascii" (or "emoticon") field. Emojis without one are copied as usual.
+# with_fallback = false
+
+# Text copied around a text emoji (after --repeat, before the name or fallback), e.g. "`" for
+# both to paste it as inline code. Html copies get them inside the markup; png emojis are left
+# alone.
+# prefix = ""
+# suffix = ""
+
+# Keep a binary copy of the parsed emojis.json in the data folder (emojis.cache) and load that
+# instead while emojis.json is unchanged. Speeds up startup with large emojis files.
+# cache = false
+
+# Offer the text snippets from snippets.json in the data folder ({"name": "text", ...})
+# snippets = true
+
+# List the five skin tone variants of every emoji that supports them
+# expand_tones = false
+
+# Command used for notifications. dunstify replaces the previous notification instead of stacking.
+# notifier = "notify-send"
+
+# Give up downloading emojis.json on the first run after this long ("30s", "2m", ...)
+# fetch_timeout = "1m"
+
+# Sound file played with paplay (or pw-play) after each successful copy
+# sound = "/usr/share/sounds/freedesktop/stereo/message.oga"
+
+# Keep using the picker last named on the command line (`rustoji bemenu`) in later runs that don't
+# name one, instead of picker.default. It's stored in last_picker in the data folder.
+# remember_picker = false
+
+# Exit quietly when rustoji is started while another one's picker is still open, e.g. from a
+# hotkey pressed twice. The lock is rustoji.lock in the data folder.
+# single_instance = false
+
+# Include details such as the clipboard tool's exit status in messages
+# verbose = false
+
+# No notifications and no warnings, only errors. Overrides verbose when both are set.
+# quiet = false
+
+# Show at most this many entries, recently used ones first. Rarely used emojis may be hidden.
+# limit = 500
+
+# Show the codepoints of unicode emojis in the picker, e.g. "👍 +1 U+1F44D". They're only
+# displayed, and can be searched for, never copied.
+# show_codepoints = false
+
+# Add each unicode emoji's group to its picker entry, so typing "animal" finds every animal. Like
+# codepoints, it's only there to be matched, never copied.
+# search_category = false
+
+# Shorten names longer than this many characters in the picker, ending them with "…". The full
+# name is still what gets copied and recorded.
+# max_name_len = 40
+
+# What to do with text typed into the picker that matches no emoji: "copy-verbatim" copies it
+# as-is (useful for kaomoji, and never recorded in history), "reject" fails with an error and
+# "ignore" exits silently
+# free_text = "copy-verbatim"
+
+# Clipboard program to use instead of wl-copy. It gets the emoji (text, html, a file:// path or
+# png data) on stdin; {mime} in the arguments is replaced by its type, e.g. "text/plain" or
+# "image/png". Arguments are split on whitespace, without quoting.
+# copy_cmd = "xclip -selection clipboard -t {mime}"
+
+# Program run after every successful copy, for clipboard managers that miss it. The copied text,
+# prefix, suffix and all (or a png emoji's path), replaces {glyph} in the arguments and is also
+# given on stdin. Failures are only reported.
+# post_copy_cmd = "cliphist store"
+
+# After copying a text emoji, also type what was copied into the focused window with wtype (or
+# ydotool), for apps that handle one of the two badly. It's copied first, then typed; png emojis
+# are only copied. Doesn't go with html, whose markup isn't meant to be typed.
+# copy_and_type = false
+
+# Seat whose clipboard wl-copy uses, for multi-seat systems. Unset uses wl-copy's default.
+# seat = "seat0"
+
+# Selection every copy goes to: "clipboard", "primary" (middle-click paste) or "both". Applies to
+# text, html, png images and file:// paths alike; ignored with copy_cmd.
+# selection = "clipboard"
+
+# "wayland" or "x11" to force the clipboard tool (wl-copy or xclip) instead of going by
+# WAYLAND_DISPLAY and DISPLAY, for XWayland or nested sessions. Same as --wayland / --x11.
+# display_server = "auto"
+
+# Copy as text/html (<span>😀</span>, or an <img> for png emojis) instead of plain text or image
+# html = false
+
+# When the picker isn't installed and rustoji runs in a terminal, fall back to a numbered list
+# on the terminal instead of failing
+# builtin_picker = false
+
+# Font --render-check looks for glyphs in, as a fontconfig pattern
+# font = "emoji"
+
+# Neither read nor write the usage history
+# no_history = false
+
+# Write history.json on a single line instead of indented, which is smaller and quicker to write
+# for histories with thousands of entries
+# compact_history = false
+
+# Multipliers for how often png and unicode emojis were used when ordering history, e.g.
+# png_weight = 2.0 to keep custom emojis ahead of unicode ones used as often
+# png_weight = 1.0
+# unicode_weight = 1.0
+
+# Count picks of the same emoji repeated within this long of each other, with nothing else picked
+# in between, as one use ("30s", "5m", ...), so rapid repeats don't take over the top of history.
+# Unset counts every pick.
+# cooldown = "1m"
+
+# How much history counts when --select finds several equally good matches: the most used emoji
+# gets this much extra, others in history proportionally less. Below 1 it only breaks ties; 0
+# turns it off.
+# select_history_boost = 0.5
+
+# What the picker matches unicode emojis by, for emojis files that carry a "description":
+# "name", "description" or "both". The name is always what's shown, and pickers match the whole
+# line, so "description" only differs from "both" for --select, which then ignores names.
+# search_field = "name"
+
+# Order of the unicode emojis listed after history and png emojis: "name" (alphabetical) or
+# "codepoint", which keeps related emojis together like most emoji keyboards
+# sort_all = "name"
+
+# After picking an emoji that comes in skin tones, open the picker again with just its tones to
+# choose one, like a long press on a phone keyboard. The tone chosen is what history records.
+# variants = false
+
+# List png emojis by name only, without icons, even in pickers that can show them
+# no_icons = false
+
+# Only the first this many png emojis, recently used ones first, get icons; the rest are listed
+# by name. Keeps the picker responsive with huge emoji folders. Unset shows all icons.
+# max_icons = 200
+
+# Include symlinked png files and folders in the png emojis folder
+# follow_symlinks = true
+
+# Skip files in the png emojis folder that aren't actually png images (costs a read per file)
+# validate_images = false
+
+# List recently added png emojis first, and how recent counts as new ("30m", "12h", "7d", ...)
+# new_first = false
+# new_window = "7d"
+
+# A profile's own png emojis folder, instead of png_dir
+# [profiles.work]
+# png_dir = "~/assets/work-emojis"
+
+# Icons shown next to the unicode emojis of a group, in pickers that show icons (fuzzel), keyed
+# by the group names --categories lists. Groups without one get no icon.
+# [category_icons]
+# "Smileys & Emotion" = "~/assets/icons/smileys.png"
+
+[picker]
+# Picker to launch: "fuzzel", "bemenu" or "wmenu"
+# default = "fuzzel"
+
+# Prompt shown in the picker's input line
+# prompt = "emoji: "
+
+# Order of the fields in emoji entries: "glyph-first" ("😀 grinning") or "name-first"
+# ("grinning 😀"). Either way the name is what's recorded in history.
+# layout = "glyph-first"
+
+# Close the picker if nothing is picked within this long ("30s", "2m", ...)
+# timeout = "1m"
+
+# How fuzzel scales icons and fonts on HiDPI outputs: "auto", "yes" or "no"
+# dpi_aware = "auto"
+
+# Window geometry, left to the picker when unset. fuzzel takes all three, bemenu and wmenu only
+# take lines. anchor is one of fuzzel's positions: "top", "center", "bottom-left", ...
+# width = 30
+# lines = 10
+# anchor = "center"
+
+# Environment variables set for the picker, e.g. to theme it. --picker-env KEY=VALUE adds more.
+# [picker.env]
+# BEMENU_OPTS = "--fn 'Noto Color Emoji 14'"
+
+# Extra arguments for a specific picker, added after the ones rustoji always passes
+# [picker.fuzzel]
+# args = ["--width", "40"]
+"#;
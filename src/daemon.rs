@@ -0,0 +1,343 @@
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+// `rustoji daemon` loads the emojis once and waits on a Unix socket; `rustoji show` asks it for a
+// pick instead of loading them itself. A client sends one request line:
+//
+//   show    run the picker and copy the pick, as a plain `rustoji` with the daemon's flags would
+//   stop    remove the socket and exit
+//
+// The daemon forks for each show, so the pick starts from the loaded emojis while history,
+// order.txt and the png folder are read afresh. The fork's stderr is the connection and its
+// stdout a pipe the daemon relays as "\0stdout <line>" lines, so the client prints what the pick
+// printed to the same stream; the daemon ends the reply with a "\0exit <code>" line once the fork
+// is done. Requests are served one at a time: a second show waits for the first picker to close.
+// When the emojis file changes the daemon answers "reloading" to the next request and
+// re-executes itself, and the client retries once the new daemon is listening.
+
+const EXIT_PREFIX: &str = "\0exit ";
+const STDOUT_PREFIX: &str = "\0stdout ";
+const RELOADING: &str = "reloading";
+// how often the emojis file is checked while no requests come in
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+// how long `show` waits for a reloading daemon to listen again
+const RELOAD_WAIT: Duration = Duration::from_secs(10);
+
+// In the runtime directory, which only the user can reach, or else the data folder. Each profile
+// gets a daemon of its own.
+pub fn socket_path(data_folder: &Path, profile: Option<&str>) -> PathBuf {
+    let file_name = match profile {
+        Some(profile) => format!("rustoji-{profile}.sock"),
+        None => "rustoji.sock".to_string(),
+    };
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(runtime_dir) if !runtime_dir.is_empty() => PathBuf::from(runtime_dir).join(file_name),
+        _ => data_folder.join(file_name),
+    }
+}
+
+// Returns true in a forked child that should go on to pick, with its output going to the client,
+// and false once a stop request was served. The daemon itself never returns otherwise.
+pub fn serve(socket_path: &Path, emojis_file_path: &Path) -> io::Result<bool> {
+    let listener = bind(socket_path)?;
+    let loaded_version = modified(emojis_file_path);
+    crate::log::debug!("daemon listening on {}", socket_path.display());
+
+    loop {
+        if !wait_readable(&listener, POLL_INTERVAL)? {
+            if modified(emojis_file_path) != loaded_version {
+                return Err(reload(listener, socket_path, emojis_file_path, None));
+            }
+            continue;
+        }
+        let mut stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                crate::log::note!("Failed to accept a connection: {err}");
+                continue;
+            }
+        };
+        // a client that never sends its request mustn't hold up the next one
+        stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+        let mut request = String::new();
+        if let Err(err) = BufReader::new(&stream).read_line(&mut request) {
+            crate::log::debug!("dropping a client that sent no request: {err}");
+            continue;
+        }
+
+        match request.trim() {
+            "show" if modified(emojis_file_path) != loaded_version => {
+                return Err(reload(
+                    listener,
+                    socket_path,
+                    emojis_file_path,
+                    Some(stream),
+                ));
+            }
+            "show" => {
+                let (output, output_writer) = match pipe() {
+                    Ok(pipe) => pipe,
+                    Err(err) => {
+                        let _ = writeln!(stream, "Failed to start a pick: {err}\n{EXIT_PREFIX}1");
+                        continue;
+                    }
+                };
+                // SAFETY: the daemon never starts a thread, so no lock can be held by one that
+                // doesn't exist in the child, and the child may go on to run any code, not only
+                // async-signal-safe calls
+                match unsafe { libc::fork() } {
+                    -1 => {
+                        let err = io::Error::last_os_error();
+                        let _ = writeln!(stream, "Failed to start a pick: {err}\n{EXIT_PREFIX}1");
+                    }
+                    0 => {
+                        // the pick reports to the client, and its end closes the connection
+                        // SAFETY: the pipe's write end and the stream are open until the block
+                        // ends, and dup2 only replaces stdout and stderr, which nothing holds
+                        // a reference to
+                        unsafe {
+                            libc::dup2(output_writer.as_raw_fd(), libc::STDOUT_FILENO);
+                            libc::dup2(stream.as_raw_fd(), libc::STDERR_FILENO);
+                        }
+                        return Ok(true);
+                    }
+                    pid => {
+                        drop(output_writer);
+                        let code = relay_stdout(pid, fs::File::from(output), &mut stream)?;
+                        crate::log::debug!("pick {pid} exited with {code}");
+                        // the client may be gone already, which only it cares about
+                        let _ = writeln!(stream, "{EXIT_PREFIX}{code}");
+                    }
+                }
+            }
+            "stop" => {
+                fs::remove_file(socket_path)?;
+                let _ = writeln!(stream, "{EXIT_PREFIX}0");
+                return Ok(false);
+            }
+            request => {
+                let _ = writeln!(stream, "Unknown request {request:?}\n{EXIT_PREFIX}2");
+            }
+        }
+    }
+}
+
+// Sends `show` to the daemon and relays its reply, returning the pick's exit code.
+pub fn show(socket_path: &Path) -> io::Result<i32> {
+    request(socket_path, "show")
+}
+
+pub fn stop(socket_path: &Path) -> io::Result<i32> {
+    request(socket_path, "stop")
+}
+
+fn request(socket_path: &Path, request: &str) -> io::Result<i32> {
+    let mut reloading_since: Option<Instant> = None;
+    loop {
+        let mut stream = match UnixStream::connect(socket_path) {
+            Ok(stream) => stream,
+            Err(_) if reloading_since.is_some_and(|since| since.elapsed() < RELOAD_WAIT) => {
+                thread::sleep(Duration::from_millis(50));
+                continue;
+            }
+            Err(err) => {
+                let msg = format!(
+                    "No rustoji daemon is listening on {} ({err}); start one with `rustoji daemon`",
+                    socket_path.display()
+                );
+                return Err(io::Error::new(err.kind(), msg));
+            }
+        };
+        writeln!(stream, "{request}")?;
+
+        let mut reloading = false;
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            if let Some(code) = line.strip_prefix(EXIT_PREFIX) {
+                return Ok(code.parse().unwrap_or(1));
+            }
+            if line == RELOADING {
+                reloading = true;
+                break;
+            }
+            match line.strip_prefix(STDOUT_PREFIX) {
+                Some(line) => println!("{line}"),
+                None => eprintln!("{line}"),
+            }
+        }
+        if !reloading {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "The rustoji daemon closed the connection without an answer",
+            ));
+        }
+        crate::log::debug!("the daemon is reloading, retrying");
+        reloading_since.get_or_insert_with(Instant::now);
+    }
+}
+
+fn bind(socket_path: &Path) -> io::Result<UnixListener> {
+    if UnixStream::connect(socket_path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!(
+                "A rustoji daemon is already listening on {}",
+                socket_path.display()
+            ),
+        ));
+    }
+    // left behind by a daemon that was killed
+    match fs::remove_file(socket_path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+    UnixListener::bind(socket_path)
+}
+
+// Starts over as a new daemon with the same arguments, which loads the emojis file again. Only
+// returns if that fails. The socket goes first, so a waiting client's retries find nothing to
+// connect to rather than the old daemon's closing listener.
+fn reload(
+    listener: UnixListener,
+    socket_path: &Path,
+    emojis_file_path: &Path,
+    waiting: Option<UnixStream>,
+) -> io::Error {
+    crate::log::note!("{} changed, reloading", emojis_file_path.display());
+    if let Err(err) = fs::remove_file(socket_path) {
+        return err;
+    }
+    drop(listener);
+    if let Some(mut stream) = waiting {
+        let _ = writeln!(stream, "{RELOADING}");
+    }
+    match env::current_exe() {
+        Ok(exe) => Command::new(exe).args(env::args_os().skip(1)).exec(),
+        Err(err) => err,
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+// The read and write ends of a new pipe, neither of which is inherited by programs run later.
+fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    // SAFETY: pipe2 writes two fds into the array, which has room for them
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: pipe2 succeeded, so both fds are open and nothing else owns them
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+// Sends the pick's stdout to the client a line at a time until the pick exits, and returns its
+// exit code. Something the pick started, like a wl-copy serving the clipboard, may keep the pipe
+// open, so once the pick is gone only what's already in the pipe is sent.
+fn relay_stdout(
+    pid: libc::pid_t,
+    mut output: fs::File,
+    stream: &mut UnixStream,
+) -> io::Result<i32> {
+    let mut pending = Vec::new();
+    let mut buffer = [0; 4096];
+    let mut closed = false;
+    let code = loop {
+        if !closed && wait_readable(&output, Duration::from_millis(100))? {
+            match output.read(&mut buffer)? {
+                0 => closed = true,
+                read => pending.extend_from_slice(&buffer[..read]),
+            }
+        } else if closed {
+            break wait_for(pid)?;
+        } else if let Some(code) = try_wait(pid)? {
+            while wait_readable(&output, Duration::ZERO)? {
+                match output.read(&mut buffer)? {
+                    0 => break,
+                    read => pending.extend_from_slice(&buffer[..read]),
+                }
+            }
+            break code;
+        }
+        while let Some(end) = pending.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = pending.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line[..end]);
+            let _ = writeln!(stream, "{STDOUT_PREFIX}{line}");
+        }
+    };
+    if !pending.is_empty() {
+        let line = String::from_utf8_lossy(&pending);
+        let _ = writeln!(stream, "{STDOUT_PREFIX}{line}");
+    }
+    Ok(code)
+}
+
+fn wait_readable(fd: &impl AsRawFd, timeout: Duration) -> io::Result<bool> {
+    let mut poll_fd = libc::pollfd {
+        fd: fd.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // SAFETY: poll_fd outlives the call, which reads and writes only that one entry, and the fd
+    // stays open as it's borrowed
+    let ready = unsafe { libc::poll(&mut poll_fd, 1, timeout.as_millis() as libc::c_int) };
+    if ready < 0 {
+        let err = io::Error::last_os_error();
+        if err.kind() == io::ErrorKind::Interrupted {
+            return Ok(false);
+        }
+        return Err(err);
+    }
+    Ok(ready > 0)
+}
+
+fn wait_for(pid: libc::pid_t) -> io::Result<i32> {
+    loop {
+        if let Some(code) = wait(pid, 0)? {
+            return Ok(code);
+        }
+    }
+}
+
+// The pick's exit code if it has exited, without waiting for it.
+fn try_wait(pid: libc::pid_t) -> io::Result<Option<i32>> {
+    wait(pid, libc::WNOHANG)
+}
+
+fn wait(pid: libc::pid_t, options: libc::c_int) -> io::Result<Option<i32>> {
+    let mut status = 0;
+    // SAFETY: status outlives the call. pid is a child this daemon forked and hasn't reaped yet,
+    // so it can't have been reused by an unrelated process
+    match unsafe { libc::waitpid(pid, &mut status, options) } {
+        0 => return Ok(None),
+        -1 => {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                return Ok(None);
+            }
+            return Err(err);
+        }
+        _ => {}
+    }
+    Ok(Some(exit_code(status)))
+}
+
+// The exit code as a shell would report it, 128 plus the signal for a killed pick.
+fn exit_code(status: libc::c_int) -> i32 {
+    if libc::WIFEXITED(status) {
+        libc::WEXITSTATUS(status)
+    } else {
+        128 + libc::WTERMSIG(status)
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::HashSet;
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::{expand_path, picker, DisplayServer, DATA_FOLDER, PNG_EMOJIS_PATH};
+
+// `rustoji doctor`: checks the setup piece by piece and prints a line per check. It runs before
+// the config is applied so a broken config is reported like anything else.
+
+type Check = Result<String, String>; // what was found, or what's wrong
+
+pub fn run(config_file_path: &Path) -> io::Result<()> {
+    let mut failed = 0;
+    let mut report = |name: &str, check: Check| {
+        let (status, detail) = match check {
+            Ok(detail) => ("ok  ", detail),
+            Err(detail) => {
+                failed += 1;
+                ("FAIL", detail)
+            }
+        };
+        println!("{status}  {name}: {detail}");
+    };
+
+    let config = Config::load(config_file_path);
+    let args = config.as_ref().ok().map(crate::parse_args);
+    report(
+        "config",
+        match (&config, &args) {
+            (Err(err), _) => Err(err.to_string()),
+            (_, Some(Err(err))) => Err(err.to_string()),
+            _ if config_file_path.exists() => Ok(config_file_path.display().to_string()),
+            _ => Ok("none, using the defaults".to_string()),
+        },
+    );
+    let args = args.and_then(Result::ok);
+
+    let data_dir = args.as_ref().map_or(DATA_FOLDER, |args| &args.data_dir);
+    let png_dir = args.as_ref().map_or(PNG_EMOJIS_PATH, |args| &args.png_dir);
+    let data_folder = expand_path(data_dir);
+    report("data folder", check_data_folder(&data_folder));
+    if let Ok(data_folder) = &data_folder {
+        let file_name = args
+            .as_ref()
+            .map_or("emojis.json", |args| &args.emojis_file_name);
+        report("emojis file", check_emojis_file(data_folder, file_name));
+    }
+    report("png emojis", check_png_folder(expand_path(png_dir)));
+
+    for picker in &picker::PICKERS {
+        let chosen = args
+            .as_ref()
+            .is_some_and(|args| args.picker.name == picker.name);
+        let check = match find_in_path(picker.name) {
+            Some(path) => Ok(path.display().to_string()),
+            None if chosen => Err("not installed, but it's the picker in use".to_string()),
+            None => Ok("not installed".to_string()),
+        };
+        report(&format!("picker {}", picker.name), check);
+    }
+    let forced = args
+        .as_ref()
+        .map_or(DisplayServer::Auto, |args| args.display_server);
+    let (tool, package) =
+        match crate::resolve_display_server(forced, |name| env::var_os(name).is_some()) {
+            DisplayServer::X11 => ("xclip", "xclip"),
+            _ => ("wl-copy", "wl-clipboard"),
+        };
+    report(
+        "clipboard",
+        find_in_path(tool)
+            .map(|path| path.display().to_string())
+            .ok_or_else(|| format!("{tool} not found; install {package}")),
+    );
+    report("session", check_session());
+
+    if failed > 0 {
+        return Err(io::Error::other(format!("{failed} checks failed")));
+    }
+    Ok(())
+}
+
+// Printed instead of launching a picker that isn't there on a first run, when there's no config
+// and no arguments to say which picker to use.
+pub fn first_run_help(picker_in_use: &str, png_folder: &Path) -> io::Error {
+    eprintln!("Welcome to rustoji! {picker_in_use}, the default picker, isn't installed.");
+    let installed: Vec<&str> = picker::PICKERS
+        .iter()
+        .map(|picker| picker.name)
+        .filter(|&name| find_in_path(name).is_some())
+        .collect();
+    if installed.is_empty() {
+        let names: Vec<&str> = picker::PICKERS.iter().map(|picker| picker.name).collect();
+        eprintln!("Install one of: {}.", names.join(", "));
+    } else {
+        eprintln!("Installed pickers: {}.", installed.join(", "));
+        eprintln!(
+            "Use one with `rustoji {}`, or run `rustoji init` and set picker.default in the config.",
+            installed[0]
+        );
+    }
+    eprintln!(
+        "Png emojis are picked up from {}, if you have any.",
+        png_folder.display()
+    );
+    eprintln!("`rustoji doctor` checks the rest of the setup.");
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{picker_in_use} not found"),
+    )
+}
+
+fn check_data_folder(data_folder: &io::Result<PathBuf>) -> Check {
+    let data_folder = data_folder.as_ref().map_err(|err| err.to_string())?;
+    if !data_folder.exists() {
+        return Ok(format!("{} (created on first run)", data_folder.display()));
+    }
+    crate::ensure_folder_exists(data_folder).map_err(|err| err.to_string())?;
+    Ok(data_folder.display().to_string())
+}
+
+fn check_emojis_file(data_folder: &Path, file_name: &str) -> Check {
+    let path = crate::emojis_file_path(data_folder, file_name);
+    if !path.exists() {
+        return Ok(format!("{} (downloaded on first run)", path.display()));
+    }
+    let (unicode_emojis, _) = crate::load_unicode_emojis(&path, false)
+        .map_err(|err| format!("{} doesn't parse: {err}", path.display()))?;
+    if unicode_emojis.is_empty() {
+        return Err(format!("{} has no emojis", path.display()));
+    }
+    Ok(format!(
+        "{} emojis in {}",
+        unicode_emojis.len(),
+        path.display()
+    ))
+}
+
+// A missing png folder is fine, png emojis are optional.
+fn check_png_folder(png_folder: io::Result<PathBuf>) -> Check {
+    let png_folder = png_folder.map_err(|err| err.to_string())?;
+    if !png_folder.exists() {
+        return Ok(format!(
+            "{} doesn't exist, no png emojis",
+            png_folder.display()
+        ));
+    }
+    let mut found = Vec::new();
+    crate::collect_png_files(&png_folder, true, &mut HashSet::new(), &mut found)
+        .map_err(|err| format!("{} can't be read: {err}", png_folder.display()))?;
+    Ok(format!(
+        "{} png emojis in {}",
+        found.len(),
+        png_folder.display()
+    ))
+}
+
+fn check_session() -> Check {
+    if let Some(display) = env::var_os("WAYLAND_DISPLAY") {
+        return Ok(format!("Wayland ({})", display.to_string_lossy()));
+    }
+    if let Some(display) = env::var_os("DISPLAY") {
+        return Ok(format!(
+            "X11 ({}), copying with xclip",
+            display.to_string_lossy()
+        ));
+    }
+    Err(
+        "no graphical session detected (WAYLAND_DISPLAY and DISPLAY are unset); emojis are \
+         picked on the terminal and printed instead of copied"
+            .to_string(),
+    )
+}
+
+pub fn find_in_path(program: &str) -> Option<PathBuf> {
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|folder| folder.join(program))
+        .find(|path| path.is_file())
+}
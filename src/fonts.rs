@@ -0,0 +1,73 @@
+use std::io;
+use std::path::PathBuf;
+use std::process::Command;
+
+// Glyph coverage of a font, read with fontconfig's command line tools.
+
+pub struct Coverage {
+    pub file: PathBuf,
+    ranges: Vec<(u32, u32)>, // inclusive codepoint ranges, as fc-query prints them
+}
+
+// The font fontconfig picks for `pattern`, e.g. "emoji" or "Noto Color Emoji".
+pub fn coverage(pattern: &str) -> io::Result<Coverage> {
+    let file = fc_output(Command::new("fc-match").args(["--format", "%{file}", pattern]))?;
+    let charset = fc_output(Command::new("fc-query").args(["--format", "%{charset}", &file]))?;
+    let ranges = charset
+        .split_whitespace()
+        .map(|range| {
+            let (start, end) = range.split_once('-').unwrap_or((range, range));
+            Some((
+                u32::from_str_radix(start, 16).ok()?,
+                u32::from_str_radix(end, 16).ok()?,
+            ))
+        })
+        .collect::<Option<_>>()
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unexpected charset from fc-query for {file}"),
+            )
+        })?;
+    Ok(Coverage {
+        file: PathBuf::from(file),
+        ranges,
+    })
+}
+
+impl Coverage {
+    // Joiners and variation selectors only shape the sequence around them, so the font doesn't
+    // need glyphs for them.
+    pub fn covers(&self, glyph: &str) -> bool {
+        glyph
+            .chars()
+            .filter(|&c| !matches!(c, '\u{200D}' | '\u{FE0E}' | '\u{FE0F}'))
+            .all(|c| {
+                self.ranges
+                    .iter()
+                    .any(|&(start, end)| (start..=end).contains(&(c as u32)))
+            })
+    }
+}
+
+fn fc_output(command: &mut Command) -> io::Result<String> {
+    let output = command.output().map_err(|err| {
+        if err.kind() == io::ErrorKind::NotFound {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "fc-match not found; --render-check needs fontconfig",
+            )
+        } else {
+            err
+        }
+    })?;
+    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || stdout.is_empty() {
+        return Err(io::Error::other(format!(
+            "fontconfig failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(stdout)
+}
@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// history.json maps each emoji name (or png file name) to how often and when it was last copied:
+// {"grinning": {"count": 3, "last_used": 1700000000}}. Files written before timestamps were
+// tracked hold bare counts, which still load.
+
+#[derive(Clone, Default)]
+pub struct Entry {
+    pub count: u32,
+    pub last_used: Option<u64>, // unix seconds
+}
+
+pub type History = HashMap<String, Entry>;
+
+// A corrupt history isn't worth failing a pick over: it's moved aside, with .bak added to its
+// name (history.json.bak), and history starts over.
+pub fn load(path: &Path) -> io::Result<History> {
+    match parse(path) {
+        Err(err)
+            if matches!(
+                err.kind(),
+                io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof
+            ) =>
+        {
+            let mut backup_path = path.as_os_str().to_owned();
+            backup_path.push(".bak");
+            let backup_path = PathBuf::from(backup_path);
+            fs::rename(path, &backup_path)?;
+            crate::log::note!(
+                "{} is corrupt ({err}); moved it to {} and started a new history",
+                path.display(),
+                backup_path.display()
+            );
+            Ok(History::new())
+        }
+        result => result,
+    }
+}
+
+fn parse(path: &Path) -> io::Result<History> {
+    let data: HashMap<String, serde_json::Value> = crate::load_json_or_default(path)?;
+    data.into_iter()
+        .map(|(name, value)| {
+            let entry = parse_entry(&value).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid history entry for {name}: {value}"),
+                )
+            })?;
+            Ok((name, entry))
+        })
+        .collect()
+}
+
+fn parse_entry(value: &serde_json::Value) -> Option<Entry> {
+    if let Some(count) = value.as_u64() {
+        return Some(Entry {
+            count: count as u32,
+            last_used: None,
+        });
+    }
+    Some(Entry {
+        count: value.get("count")?.as_u64()? as u32,
+        last_used: value
+            .get("last_used")
+            .and_then(|last_used| last_used.as_u64()),
+    })
+}
+
+// Pretty-printed unless `compact`, which is smaller and quicker to write for big histories.
+pub fn save(path: &Path, history: &History, compact: bool) -> io::Result<()> {
+    let data: serde_json::Map<String, serde_json::Value> = history
+        .iter()
+        .map(|(name, entry)| {
+            let mut value = serde_json::json!({ "count": entry.count });
+            if let Some(last_used) = entry.last_used {
+                value["last_used"] = last_used.into();
+            }
+            (name.clone(), value)
+        })
+        .collect();
+
+    // write to a temporary file and rename it over the old one so an interrupted write never
+    // leaves a truncated history behind
+    let temp_path = path.with_extension("json.tmp");
+    let mut file = io::BufWriter::new(fs::File::create(&temp_path)?);
+    if compact {
+        serde_json::to_writer(&mut file, &data)?;
+    } else {
+        serde_json::to_writer_pretty(&mut file, &data)?;
+    }
+    file.flush()?;
+    fs::rename(temp_path, path)
+}
+
+// With a cooldown, picking the same emoji again right after itself (nothing else picked in
+// between) within the window only refreshes last_used, so rapid repeats don't push it to the top.
+pub fn record(history: &mut History, name: String, cooldown: Option<Duration>) {
+    let now = now();
+    let last_used = history.get(&name).and_then(|entry| entry.last_used);
+    let repeat = match (cooldown, last_used) {
+        (Some(cooldown), Some(last_used)) => {
+            // a tie with another emoji's timestamp counts as something else picked in between
+            let picked_since = history
+                .iter()
+                .any(|(key, entry)| *key != name && entry.last_used >= Some(last_used));
+            !picked_since && now.saturating_sub(last_used) < cooldown.as_secs()
+        }
+        _ => false,
+    };
+    let entry = history.entry(name).or_default();
+    if !repeat {
+        entry.count += 1;
+    }
+    entry.last_used = Some(now);
+}
+
+// copies.txt holds a single number: every successful copy, including free text and emojis whose
+// history entries were since removed. It's kept out of history.json on purpose.
+pub fn load_copies(path: &Path) -> io::Result<u64> {
+    match fs::read_to_string(path) {
+        Ok(text) => text.trim().parse().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} doesn't hold a number", path.display()),
+            )
+        }),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(0),
+        Err(err) => Err(err),
+    }
+}
+
+pub fn record_copy(path: &Path) -> io::Result<()> {
+    let copies = load_copies(path)? + 1;
+    fs::write(path, format!("{copies}\n"))
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+// "just now", "5m ago", "2h ago", "3d ago"
+pub fn format_relative(timestamp: u64) -> String {
+    let elapsed = now().saturating_sub(timestamp);
+    match elapsed {
+        0..60 => "just now".to_string(),
+        60..3600 => format!("{}m ago", elapsed / 60),
+        3600..86400 => format!("{}h ago", elapsed / 3600),
+        _ => format!("{}d ago", elapsed / 86400),
+    }
+}
+
+// "2024-05-01 13:37" in the local timezone
+pub fn format_local_time(timestamp: u64) -> String {
+    let time = timestamp as libc::time_t;
+    // SAFETY: tm is plain old data, and both pointers stay valid for the duration of the calls
+    let tm = unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&time, &mut tm).is_null() {
+            return timestamp.to_string();
+        }
+        tm
+    };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min
+    )
+}
@@ -0,0 +1,51 @@
+use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+// Debug traces on stderr, turned on like env_logger would be: RUST_LOG=debug, RUST_LOG=trace or
+// RUST_LOG=rustoji=debug. Everything else, including an unset RUST_LOG, stays quiet.
+
+pub fn enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| {
+        env::var("RUST_LOG").is_ok_and(|directives| {
+            directives.split(',').any(|directive| {
+                let level = match directive.split_once('=') {
+                    Some(("rustoji", level)) => level,
+                    Some(_) => return false,
+                    None => directive,
+                };
+                level.eq_ignore_ascii_case("debug") || level.eq_ignore_ascii_case("trace")
+            })
+        })
+    })
+}
+
+// --quiet silences warnings and other informational messages; errors are still reported.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+pub fn set_quiet() {
+    QUIET.store(true, Ordering::Relaxed);
+}
+
+pub fn quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if crate::log::enabled() {
+            eprintln!("[rustoji] {}", format_args!($($arg)*));
+        }
+    };
+}
+
+macro_rules! note {
+    ($($arg:tt)*) => {
+        if !crate::log::quiet() {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+pub(crate) use {debug, note};
@@ -1,238 +1,4738 @@
+mod cache;
+mod config;
+mod daemon;
+mod doctor;
+mod fonts;
+mod history;
+mod log;
+mod picker;
+mod tones;
+
+use config::{Config, Value};
 use expanduser::expanduser;
-use serde_json;
-use std::collections::HashMap;
+use picker::Picker;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
+use std::ffi::OsStr;
 use std::fs;
-use std::io::Read;
 use std::io::{self, Result, Write};
+use std::io::{BufRead, IsTerminal, Read};
+use std::os::unix::process::ExitStatusExt;
 use std::path::{Path, PathBuf};
 use std::process::ExitStatus;
-use std::process::{Command, Stdio};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 
 // Constants
 const PNG_EMOJIS_PATH: &str = "~/assets/emojis";
 const DATA_FOLDER: &str = "~/.local/share/rustoji";
-const SUPPORTED_PICKERS: [&str; 2] = ["fuzzel", "bemenu"];
+const CONFIG_FILE: &str = "~/.config/rustoji/config.toml";
+const MAX_CODEPOINTS_SHOWN: usize = 16; // --show-codepoints skips longer text
+const FIELD_SEPARATOR: char = '\t'; // between the glyph and the name in picker entries
 const UNICODE_EMOJIS_FILE_URL: &str =
     "https://raw.githubusercontent.com/Zai-Kun/rustoji/refs/heads/master/emojis.json";
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const DEFAULT_NOTIFIER: &str = "notify-send";
+const NOTIFICATION_ID: &str = "7271"; // lets dunstify replace the previous toast instead of stacking
+const DEFAULT_NEW_WINDOW: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+enum Selection {
+    Unicode { glyph: String, name: String },
+    Png(String),  // file name inside the png emojis folder
+    Text(String), // typed into the picker without matching an entry
+}
+
+impl Selection {
+    // what gets copied and shown in the notification
+    fn text(&self) -> &str {
+        match self {
+            Selection::Unicode { glyph, .. } => glyph,
+            Selection::Png(file_name) => file_name,
+            Selection::Text(text) => text,
+        }
+    }
+
+    // the name --with-name copies after the emoji, and --copy-name instead of it; png file names
+    // lose their folder and extension and have `_` and `-` turned into spaces
+    fn display_name(&self) -> Option<String> {
+        match self {
+            Selection::Unicode { name, .. } => Some(name.clone()),
+            Selection::Png(file_name) => {
+                let stem = Path::new(file_name).file_stem()?.to_string_lossy();
+                Some(stem.replace(['_', '-'], " "))
+            }
+            Selection::Text(_) => None,
+        }
+    }
+
+    fn history_key(&self) -> Option<&str> {
+        match self {
+            Selection::Unicode { name, .. } => Some(name),
+            Selection::Png(file_name) => Some(file_name),
+            Selection::Text(_) => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum FreeText {
+    CopyVerbatim, // copy it as-is, handy for kaomoji
+    Reject,       // fail with an error
+    Ignore,       // exit silently
+}
+
+// Which field of a unicode entry comes first in the picker. The name after the glyph is the
+// default; name-first suits searching by name.
+#[derive(Clone, Copy, PartialEq)]
+enum Layout {
+    GlyphFirst,
+    NameFirst,
+}
+
+// What the picker's matcher gets to search besides the visible name: descriptions are added to
+// the matched field of unicode entries, and --select ignores the name with `description`.
+#[derive(Clone, Copy, PartialEq)]
+enum SearchField {
+    Name,
+    Description,
+    Both,
+}
+
+// How the unicode emojis after history and png emojis are ordered. Codepoint order keeps related
+// emojis together, close to the layout of most emoji keyboards.
+#[derive(Clone, Copy)]
+enum SortOrder {
+    Name,
+    Codepoint,
+}
+
+// Which clipboard tool copies: wl-copy on Wayland, xclip on X11. Auto goes by WAYLAND_DISPLAY and
+// DISPLAY, which also decide whether there's a clipboard at all.
+#[derive(Clone, Copy, PartialEq)]
+enum DisplayServer {
+    Auto,
+    Wayland,
+    X11,
+}
+
+// What copying a png emoji puts on the clipboard: its file:// path, which pastes in most places,
+// or the image itself, for apps that take images.
+#[derive(Clone, Copy, PartialEq)]
+enum CopyMode {
+    Path,
+    Image,
+}
+
+// Which wl-copy selection every copy goes to, whether it is text, html, a png or a file:// uri.
+#[derive(Clone, Copy, PartialEq)]
+enum ClipboardSelection {
+    Clipboard,
+    Primary,
+    Both,
+}
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+enum Mode {
+    Pick,
+    Init,                             // write a default config file
+    Stats,                            // print usage history
+    ExportStats(String),              // write usage history to a CSV file
+    List,                             // print every loaded emoji
+    Categories,                       // print the emoji groups and png subfolders
+    Pickers,                          // print the supported pickers and what rustoji uses of each
+    RenderCheck,                      // print the emojis the font has no glyphs for
+    Bench,                            // time startup up to the point the picker would be launched
+    OpenConfig,                       // open the config file's folder in the file manager
+    OpenData,                         // open the data folder in the file manager
+    DbInfo,                           // print where emojis.json came from and when
+    PruneHistory,                     // drop png emojis that no longer exist from history
+    ResetCount(String),               // set one emoji's history count to zero, keeping its entry
+    ImportPack(String), // copy a folder of exported custom emojis into the png emojis folder
+    AddEmoji(String, Option<String>), // download a png emoji from a url, optionally naming it
+    ShowConfig,         // print the settings resolved from the config, environment and flags
+    Daemon,             // load the emojis once and pick whenever `rustoji show` asks
+    Show,               // ask the daemon for a pick
+    StopDaemon,         // ask the daemon to exit
+}
+
+struct Args {
+    mode: Mode,
+    picker: &'static Picker,
+    copy_mode: CopyMode,       // how png emojis are copied
+    new_first: bool,           // surface recently added png emojis first
+    new_window: Duration,      // how old a png emoji can be and still count as new
+    no_history: bool,          // neither read nor write history.json
+    newline: bool,             // append a newline to copied text emojis
+    force: bool,               // allow init and add-emoji to overwrite an existing file
+    dpi_aware: Option<String>, // fuzzel's icon/font scaling mode: auto, yes or no
+    expand_tones: bool,        // list every skin tone variant of modifiable emojis
+    limit: Option<usize>,      // cap on picker entries; rarely used emojis may not be listed
+    notifier: String,          // notify-send, dunstify or anything taking the same arguments
+    verbose: bool,
+    json: bool,            // machine-readable output for --list
+    query: Option<String>, // initial filter text, for pickers that take one
+    snippets: bool,        // load snippets.json
+    free_text: FreeText,   // what to do with typed text that matches no entry
+    prompt: Option<String>,
+    html: bool,                      // copy as text/html for rich-text editors
+    follow_symlinks: bool,           // descend into symlinked png files and folders
+    validate_images: bool,           // skip png files without a png signature
+    no_icons: bool,                  // list png emojis by name even if the picker can show icons
+    extra_emojis_files: Vec<String>, // merged over emojis.json in order
+    width: Option<String>,           // picker geometry, unset keeps the picker's own default
+    lines: Option<String>,
+    anchor: Option<String>,
+    max_name_len: Option<usize>, // longer names are shortened in the picker
+    with_name: bool,             // copy the name after the emoji, as text or html
+    with_fallback: bool,         // copy the emoji's ascii fallback after it, e.g. "😀 :D"
+    sound: Option<String>,       // played after a successful copy
+    data_dir: String,            // emojis.json, history.json and friends
+    emojis_file_name: String,    // emojis.json unless the config names another file
+    history_file_name: String,   // history.json likewise
+    png_dir: String,
+    repeat: usize,        // copies of a text emoji to copy in a row
+    cache: bool,          // load emojis.json from the binary emojis.cache while it's up to date
+    builtin_picker: bool, // prompt on the terminal when the picker isn't installed
+    seat: Option<String>, // passed to wl-copy on multi-seat systems
+    selection: ClipboardSelection,
+    display_server: DisplayServer, // --wayland or --x11 instead of detecting the session
+    font: String,                  // fontconfig pattern --render-check checks against
+    picker_env: Vec<(String, String)>, // environment variables for the picker
+    copy_cmd: Option<String>,      // clipboard program to use instead of wl-copy
+    post_copy_cmd: Option<String>, // run after a successful copy, e.g. to store it in cliphist
+    since: Option<Duration>,       // only list emojis used this recently in the history section
+    recents_only: bool,            // list only history entries, while there are any
+    quiet: bool,                   // no notifications or warnings, only errors; wins over verbose
+    layout: Layout,                // glyph or name first in unicode entries
+    show_codepoints: bool,         // list U+XXXX codepoints with unicode emojis
+    category_icons: HashMap<String, String>, // group -> icon shown with its unicode emojis
+    fetch_timeout: Option<Duration>, // give up downloading emojis.json after this long
+    picker_timeout: Option<Duration>, // close the picker after this long, copying nothing
+    select: Option<String>,        // match this instead of asking the picker
+    first: bool,                   // with --select, take the first of several equally good matches
+    remember_picker: bool,         // default to the picker last given on the command line
+    picker_given: bool,            // the picker was named on the command line
+    copy_name: bool,               // copy the emoji's name instead of the emoji
+    no_record: bool,               // list history as usual but leave this pick out of it
+    single_instance: bool,         // exit quietly while another rustoji is picking
+    no_picker: bool,               // print the picker entries to stdout instead of running a picker
+    png_weight: f64,               // multiplies png emojis' history counts when ordering history
+    unicode_weight: f64,           // the same for unicode emojis
+    search_field: SearchField,
+    sort_all: SortOrder,
+    variants: bool,             // pick a toneable emoji's skin tone in a second picker
+    compact_history: bool,      // write history.json without indentation
+    cooldown: Option<Duration>, // repeat picks of the same emoji within this count once
+    select_history_boost: f64,  // how much history lifts an emoji among --select's matches
+    stress: Option<usize>,      // synthetic emojis added for --bench-startup
+    prefix: String,             // copied before a text emoji, e.g. a backtick
+    suffix: String,             // and after it
+    copy_and_type: bool,        // also type text emojis after copying them
+    profile: Option<String>,    // data files under <data_dir>/profiles/<name>
+    max_icons: Option<usize>,   // only this many png entries get icons, the rest are plain labels
+    search_category: bool,      // add each unicode emoji's group to its entry for the matcher
+    image_size: Option<usize>,  // png emojis are copied scaled to fit this many pixels
+    dmenu_passthrough: bool,    // print the entries and read the pick back from stdin
+    selection_fd: Option<i32>,  // like dmenu_passthrough, but the pick is read from this fd
+    print_result: bool,         // end with a status line on stdout for wrapper scripts
+    picker_args: Vec<String>,   // extra arguments for the picker from its [picker.<name>] section
+}
 
 fn main() -> Result<()> {
-    let expanded_png_emojis_path = expanduser(PNG_EMOJIS_PATH)?;
-    let expanded_data_folder_path = expanduser(DATA_FOLDER)?;
+    let started = Instant::now();
+    // the config file's location has to be known before the config, and so the rest of the
+    // arguments, can be read
+    let explicit_config_file = early_flag_value("--config");
+    let config_file = explicit_config_file
+        .clone()
+        .unwrap_or_else(|| CONFIG_FILE.to_string());
+    let config_file_path = expand_path(&config_file)?;
+    // checked before loading the config, which is exactly what may be broken
+    if env::args()
+        .skip(1)
+        .any(|arg| arg == "--reset-config-to-defaults")
+    {
+        let data_folder = early_flag_value("--data-dir").unwrap_or_else(|| DATA_FOLDER.to_string());
+        return reset_config(&config_file_path, &expand_path(&data_folder)?);
+    }
+    if env::args().nth(1).is_some_and(|arg| arg == "doctor") {
+        return doctor::run(&config_file_path);
+    }
+    if env::args().nth(1).is_some_and(|arg| arg == "format-db") {
+        let path = env::args().nth(2).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "format-db needs the path of an emojis file",
+            )
+        })?;
+        return format_db(&expand_path(&path)?);
+    }
+    let config = Config::load(&config_file_path)?;
+    let mut args = parse_args(&config)?;
+    // unlike the default location, a config file named with --config has to exist, unless it's
+    // the one `init` is asked to write
+    if explicit_config_file.is_some()
+        && !config_file_path.exists()
+        && !matches!(args.mode, Mode::Init)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Config file {} not found", config_file_path.display()),
+        ));
+    }
+    if args.quiet {
+        log::set_quiet();
+    }
+    if let Mode::Pick | Mode::Daemon = args.mode {
+        check_display_server(&args)?;
+    }
+
+    let expanded_png_emojis_path = expand_path(&args.png_dir)?;
+    let expanded_data_folder_path = expand_path(&args.data_dir)?;
+
+    if let Mode::Pickers = args.mode {
+        for picker in &picker::PICKERS {
+            let chosen = if picker.name == args.picker.name {
+                "*"
+            } else {
+                " "
+            };
+            println!(
+                "{chosen} {:<8}{}",
+                picker.name,
+                picker.features().join(", ")
+            );
+        }
+        return Ok(());
+    }
+
+    if let Mode::ShowConfig = args.mode {
+        show_config(&args);
+        return Ok(());
+    }
 
-    let unicode_emojis_file_path = expanded_data_folder_path.join("emojis.json");
-    let history_file_path = expanded_data_folder_path.join("history.json");
+    if let Mode::Show | Mode::StopDaemon = args.mode {
+        let socket_path = daemon::socket_path(&expanded_data_folder_path, args.profile.as_deref());
+        let code = match args.mode {
+            Mode::Show => daemon::show(&socket_path)?,
+            _ => daemon::stop(&socket_path)?,
+        };
+        if code != 0 {
+            std::process::exit(code);
+        }
+        return Ok(());
+    }
+
+    if let Mode::Init = args.mode {
+        return init_config(&config_file_path, &expanded_data_folder_path, args.force);
+    }
+
+    if let Mode::ImportPack(folder) = &args.mode {
+        return import_pack(&expand_path(folder)?, &expanded_png_emojis_path);
+    }
+
+    if let Mode::AddEmoji(url, name) = &args.mode {
+        return add_emoji(url, name.as_deref(), &expanded_png_emojis_path, &args);
+    }
+
+    if let Mode::OpenConfig | Mode::OpenData = args.mode {
+        let folder = match args.mode {
+            Mode::OpenConfig => config_file_path.parent().unwrap_or(Path::new("/")),
+            _ => &expanded_data_folder_path,
+        };
+        ensure_folder_exists(folder)?;
+        open_folder(folder);
+        return Ok(());
+    }
+
+    // with nothing configured, explain the setup rather than download emojis.json only to fail
+    // launching the picker
+    if env::args().len() == 1
+        && !config_file_path.exists()
+        && doctor::find_in_path(args.picker.name).is_none()
+    {
+        return Err(doctor::first_run_help(
+            args.picker.name,
+            &expanded_png_emojis_path,
+        ));
+    }
+
+    let unicode_emojis_file_path =
+        emojis_file_path(&expanded_data_folder_path, &args.emojis_file_name);
+    log::debug!("config file: {}", config_file_path.display());
+    log::debug!("data folder: {}", expanded_data_folder_path.display());
+    log::debug!("png emojis folder: {}", expanded_png_emojis_path.display());
+    log::debug!("emojis file: {}", unicode_emojis_file_path.display());
+    let kaomoji_file_path = expanded_data_folder_path.join("kaomoji.json");
+    let snippets_file_path = expanded_data_folder_path.join("snippets.json");
+    let history_file_path = expanded_data_folder_path.join(&args.history_file_name);
+    let order_file_path = expanded_data_folder_path.join("order.txt");
+    let copies_file_path = expanded_data_folder_path.join("copies.txt");
 
     ensure_folder_exists(&expanded_data_folder_path)?;
 
+    if args.remember_picker {
+        let last_picker_file_path = expanded_data_folder_path.join("last_picker");
+        remember_picker(&mut args, &config, &last_picker_file_path)?;
+    }
+
+    // held until rustoji exits
+    let _instance_lock = match args.mode {
+        Mode::Pick if args.single_instance => {
+            match lock_instance(&expanded_data_folder_path.join("rustoji.lock"))? {
+                Some(lock) => Some(lock),
+                None => return Ok(()),
+            }
+        }
+        _ => None,
+    };
+
     if !unicode_emojis_file_path.exists() {
-        fetch_unicode_emojis_file(&unicode_emojis_file_path)?;
+        fetch_unicode_emojis_file(&unicode_emojis_file_path, args.fetch_timeout)?;
+    }
+
+    let mut history = if args.no_history {
+        history::History::new()
+    } else {
+        history::load(&history_file_path)?
+    };
+    if let Mode::PruneHistory = args.mode {
+        return prune_history(
+            &mut history,
+            &history_file_path,
+            &expanded_png_emojis_path,
+            args.compact_history,
+        );
+    }
+
+    let cache_file_path = expanded_data_folder_path.join("emojis.cache");
+    let cached = args
+        .cache
+        .then(|| cache::load(&cache_file_path, &unicode_emojis_file_path))
+        .flatten();
+    let (mut unicode_emojis, mut details) = match cached {
+        Some(cached) => cached,
+        None => {
+            let (unicode_emojis, details) =
+                load_unicode_emojis(&unicode_emojis_file_path, args.verbose)?;
+            if args.cache {
+                // a cache that can't be written only costs the next launch some time
+                let saved = cache::save(
+                    &cache_file_path,
+                    &unicode_emojis_file_path,
+                    &unicode_emojis,
+                    &details,
+                );
+                if let Err(err) = saved {
+                    log::note!("Failed to write {}: {err}", cache_file_path.display());
+                }
+            }
+            (unicode_emojis, details)
+        }
+    };
+    if let Mode::DbInfo = args.mode {
+        return print_db_info(&unicode_emojis_file_path, &unicode_emojis);
+    }
+    // every .json file in emojis.d adds a pack, in file name order
+    for pack_path in emoji_pack_paths(&expanded_data_folder_path.join("emojis.d"))? {
+        log::debug!("emoji pack: {}", pack_path.display());
+        let (pack, pack_details) = load_unicode_emojis(&pack_path, args.verbose)?;
+        merge_emoji_pack(&mut unicode_emojis, &mut details, pack, pack_details);
+    }
+    // extra emojis files are layered on top, a later file replacing an earlier one's emoji
+    for extra_emojis_file in &args.extra_emojis_files {
+        let path = expand_path(extra_emojis_file)?;
+        if !path.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Emojis file {} not found", path.display()),
+            ));
+        }
+        let (extra_emojis, extra_details) = load_unicode_emojis(&path, args.verbose)?;
+        unicode_emojis.extend(extra_emojis);
+        details.groups.extend(extra_details.groups);
+        details.keywords.extend(extra_details.keywords);
+        details.descriptions.extend(extra_details.descriptions);
+        details.fallbacks.extend(extra_details.fallbacks);
+        details.aliases.extend(extra_details.aliases);
+    }
+    // before kaomoji and snippets are merged in, those are text rather than emojis
+    if let Mode::RenderCheck = args.mode {
+        return match render_check(&unicode_emojis, &args.font) {
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+            result => result,
+        };
+    }
+
+    merge_text_entries(&mut unicode_emojis, &kaomoji_file_path, "kaomoji")?;
+    if args.snippets {
+        merge_text_entries(&mut unicode_emojis, &snippets_file_path, "snippet")?;
+    }
+    if let Some(count) = args.stress {
+        add_synthetic_emojis(&mut unicode_emojis, count);
+    }
+    let loaded = started.elapsed();
+
+    // `rustoji daemon` waits here with the emojis loaded, and each `rustoji show` carries on from
+    // this point in a fork of it, reading history again since earlier picks changed it
+    if let Mode::Daemon = args.mode {
+        let socket_path = daemon::socket_path(&expanded_data_folder_path, args.profile.as_deref());
+        if !daemon::serve(&socket_path, &unicode_emojis_file_path)? {
+            return Ok(());
+        }
+        args.mode = Mode::Pick;
+        if !args.no_history {
+            history = history::load(&history_file_path)?;
+        }
     }
 
-    let mut history: HashMap<String, u32> = load_json_or_default(&history_file_path)?;
-    let mut sorted_history: Vec<(&String, &u32)> = history.iter().collect();
-    sorted_history.sort_by(|a, b| b.1.cmp(a.1));
+    // With --since, older entries (and ones recorded before timestamps were kept) drop out of the
+    // history section and are listed with everything else.
+    let cutoff = args
+        .since
+        .map(|since| history::now().saturating_sub(since.as_secs()));
+    let mut sorted_history: Vec<(&String, &history::Entry)> = history
+        .iter()
+        .filter(|(_, entry)| match cutoff {
+            Some(cutoff) => entry.last_used.is_some_and(|last_used| last_used >= cutoff),
+            None => true,
+        })
+        .collect();
+    // png and unicode counts are weighed separately so either kind can be pushed ahead
+    let weighted = |key: &str, entry: &history::Entry| {
+        let weight = if has_png_extension(Path::new(key)) {
+            args.png_weight
+        } else {
+            args.unicode_weight
+        };
+        entry.count as f64 * weight
+    };
+    sorted_history
+        .sort_by(|&(a_key, a), &(b_key, b)| weighted(b_key, b).total_cmp(&weighted(a_key, a)));
     let sorted_history: Vec<&String> = sorted_history.iter().map(|&(key, _)| key).collect();
+    log::debug!("loaded {} history entries", sorted_history.len());
 
-    let unicode_emojis: HashMap<String, String> = load_json_or_default(&unicode_emojis_file_path)?;
-    let png_emojis = collect_png_emojis_and_filter(&expanded_png_emojis_path, &sorted_history)?;
+    if let Mode::ResetCount(emoji) = &args.mode {
+        return reset_count(
+            &mut history,
+            &history_file_path,
+            &unicode_emojis,
+            &details.aliases,
+            emoji,
+            args.compact_history,
+        );
+    }
 
-    let (picker, copy_png_emoji_path) = parse_args();
+    if let Mode::Stats = args.mode {
+        let copies = history::load_copies(&copies_file_path)?;
+        print_stats(&args, &sorted_history, &history, &unicode_emojis, copies);
+        return Ok(());
+    }
 
-    let output = run_picker(
-        &picker,
-        &unicode_emojis,
-        &png_emojis,
-        &sorted_history,
-        &expanded_png_emojis_path,
-    )?;
+    if let Mode::ExportStats(path) = &args.mode {
+        return export_stats(
+            &expand_path(path)?,
+            &sorted_history,
+            &history,
+            &unicode_emojis,
+            &expanded_png_emojis_path,
+        );
+    }
 
-    if output.is_empty() {
+    if let Mode::Categories = args.mode {
+        let png_emojis =
+            collect_png_emojis_and_filter(&expanded_png_emojis_path, &Vec::new(), None, &args)?;
+        print_categories(
+            &unicode_emojis,
+            &details.groups,
+            &png_emojis,
+            &expanded_png_emojis_path,
+        );
         return Ok(());
     }
 
-    let (emoji, emoji_name) = if output.ends_with(".png") {
-        (output.clone(), output.clone())
+    if let Mode::List = args.mode {
+        let png_emojis =
+            collect_png_emojis_and_filter(&expanded_png_emojis_path, &Vec::new(), None, &args)?;
+        return match print_emoji_list(
+            &args,
+            &unicode_emojis,
+            &details.groups,
+            &png_emojis,
+            &expanded_png_emojis_path,
+        ) {
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+            result => result,
+        };
+    }
+
+    // names in order.txt go first, in that order, ahead of the most used ones
+    let order = load_order(&order_file_path)?;
+    let ordered: HashSet<&String> = order.iter().collect();
+    let sorted_history: Vec<&String> = order
+        .iter()
+        .chain(
+            sorted_history
+                .into_iter()
+                .filter(|key| !ordered.contains(key)),
+        )
+        .collect();
+
+    let new_window = args.new_first.then_some(args.new_window);
+    let png_emojis = if args.recents_only && !sorted_history.is_empty() {
+        // history's png emojis are listed from history itself, no need to scan the folder
+        Vec::new()
     } else {
-        match output.split_once(' ') {
-            Some((emoji, emoji_name)) => (emoji.to_string(), emoji_name.to_string()),
-            None => {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    "Invalid emoji format",
-                ))
+        collect_png_emojis_and_filter(
+            &expanded_png_emojis_path,
+            &sorted_history,
+            new_window,
+            &args,
+        )?
+    };
+
+    let png_names = load_png_names(&expanded_png_emojis_path)?;
+
+    if unicode_emojis.is_empty() {
+        log::note!(
+            "No unicode emojis found in {}. Delete it to re-download, or check that {} serves a valid emojis file.",
+            unicode_emojis_file_path.display(),
+            UNICODE_EMOJIS_FILE_URL
+        );
+        if png_emojis.is_empty() && sorted_history.is_empty() {
+            notify(
+                &args,
+                None,
+                "No emojis to pick from: emojis.json is empty and no png emojis were found",
+            );
+            return Ok(());
+        }
+    }
+
+    if let Mode::Bench = args.mode {
+        let scanned = started.elapsed();
+        let entries = picker_entries(
+            &args,
+            &unicode_emojis,
+            &details,
+            &png_emojis,
+            &png_names,
+            &sorted_history,
+            &expanded_png_emojis_path,
+        );
+        let built = started.elapsed();
+        let mut stages = vec![
+            ("loading emojis and history", loaded),
+            ("scanning png emojis", scanned - loaded),
+            ("building picker entries", built - scanned),
+        ];
+        // with --stress, also time handing the entries to a picker that only reads them
+        if args.stress.is_some() {
+            let mut stub_picker = Command::new("cat")
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .spawn()?;
+            if let Some(mut stdin) = stub_picker.stdin.take() {
+                for entry in &entries {
+                    writeln!(stdin, "{entry}")?;
+                }
             }
+            stub_picker.wait()?;
+            stages.push(("writing to the picker", started.elapsed() - built));
         }
+        print_bench(&stages);
+        println!(
+            "{} entries ready in {:.2?}",
+            entries.len(),
+            started.elapsed()
+        );
+        return Ok(());
+    }
+
+    // for a selector of the user's own, whose pick comes back with --select
+    if args.no_picker && args.select.is_none() && args.selection_fd.is_none() {
+        let entries = picker_entries(
+            &args,
+            &unicode_emojis,
+            &details,
+            &png_emojis,
+            &png_names,
+            &sorted_history,
+            &expanded_png_emojis_path,
+        );
+        return match print_entries(&entries) {
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+            result => result,
+        };
+    }
+
+    let output = if let Some(query) = &args.select {
+        let entries = picker_entries(
+            &args,
+            &unicode_emojis,
+            &details,
+            &png_emojis,
+            &png_names,
+            &sorted_history,
+            &expanded_png_emojis_path,
+        );
+        select_entry(&args, &entries, query, &history, &png_names)?
+    } else if args.dmenu_passthrough || args.selection_fd.is_some() {
+        let entries = picker_entries(
+            &args,
+            &unicode_emojis,
+            &details,
+            &png_emojis,
+            &png_names,
+            &sorted_history,
+            &expanded_png_emojis_path,
+        );
+        dmenu_passthrough(&entries, args.selection_fd)?
+    } else {
+        catch_interrupts();
+        run_picker(&args, || {
+            picker_entries(
+                &args,
+                &unicode_emojis,
+                &details,
+                &png_emojis,
+                &png_names,
+                &sorted_history,
+                &expanded_png_emojis_path,
+            )
+        })?
     };
 
-    let status_code =
-        copy_emoji_to_clipboard(&emoji, &expanded_png_emojis_path, copy_png_emoji_path)?;
-    notify(&format!("Copied: {}", status_code));
+    if output.is_empty() || INTERRUPTED.load(Ordering::SeqCst) {
+        print_result(&args, "cancelled", &[]);
+        return Ok(());
+    }
+
+    let Some(mut selection) = parse_selection(
+        &output,
+        &args,
+        &unicode_emojis,
+        &png_names,
+        &expanded_png_emojis_path,
+    )?
+    else {
+        print_result(&args, "cancelled", &[]);
+        return Ok(());
+    };
+
+    // like a long press on an emoji keyboard: a toneable emoji opens a second picker with just
+    // its tones, and the one chosen there is what's copied and recorded
+    if let Selection::Unicode { glyph, name } = &selection {
+        // the passthrough's selector has already had its one round
+        if args.variants
+            && args.select.is_none()
+            && !args.dmenu_passthrough
+            && args.selection_fd.is_none()
+            && tones::is_modifiable(glyph)
+        {
+            let variants = std::iter::once((name.clone(), glyph.clone()))
+                .chain(tones::toned_variants(name, glyph))
+                .map(|(name, glyph)| unicode_entry(&args, &glyph, &name, None, None, None))
+                .collect();
+            let output = run_picker(&args, || variants)?;
+            if output.is_empty() || INTERRUPTED.load(Ordering::SeqCst) {
+                print_result(&args, "cancelled", &[]);
+                return Ok(());
+            }
+            let variant = parse_selection(
+                &output,
+                &args,
+                &unicode_emojis,
+                &png_names,
+                &expanded_png_emojis_path,
+            )?;
+            match variant {
+                Some(variant) => selection = variant,
+                None => {
+                    print_result(&args, "cancelled", &[]);
+                    return Ok(());
+                }
+            }
+        }
+    }
 
-    *history.entry(emoji_name).or_insert(0) += 1;
+    if clipboard_available(&args) {
+        let fallback = match &selection {
+            Selection::Unicode { name, .. } if args.with_fallback => ascii_fallback(&details, name),
+            _ => None,
+        };
+        if let Err(err) = copy_and_notify(&args, &selection, fallback, &expanded_png_emojis_path) {
+            print_result(&args, "failed", &[&err.to_string()]);
+            return Err(err);
+        }
+        let [kind, value] = result_fields(&selection, &expanded_png_emojis_path);
+        print_result(&args, "copied", &[&kind, &value]);
+    } else {
+        // a TTY or greetd session has nothing to copy to, so hand the emoji over on stdout
+        log::note!("No clipboard outside a graphical session, printing the emoji instead");
+        match &selection {
+            Selection::Png(file_name) => {
+                println!("{}", expanded_png_emojis_path.join(file_name).display())
+            }
+            _ => println!("{}", selection.text()),
+        }
+        let [kind, value] = result_fields(&selection, &expanded_png_emojis_path);
+        print_result(&args, "printed", &[&kind, &value]);
+        // nothing was copied, so neither copies.txt nor history counts it
+        return Ok(());
+    }
+
+    if !args.no_history {
+        history::record_copy(&copies_file_path)?;
+    }
+    let record = !args.no_history && !args.no_record;
+    let Some(history_key) = selection.history_key().filter(|_| record) else {
+        return Ok(());
+    };
 
-    let file = fs::File::create(&history_file_path)?;
-    serde_json::to_writer_pretty(file, &history)?;
+    history::record(
+        &mut history,
+        canonical_name(&details.aliases, history_key),
+        args.cooldown,
+    );
+    history::save(&history_file_path, &history, args.compact_history)?;
 
     Ok(())
 }
 
-fn notify(msg: &str) {
-    Command::new("notify-send")
-        .args(&[msg, "-t", "1000"])
-        .status()
-        .unwrap();
+// --print-result ends the run with one line on stdout saying how it went, tab-separated:
+//
+//   copied<TAB>unicode|png|text<TAB><glyph, png path or text>
+//   printed<TAB>...        the same, printed on stdout for lack of a clipboard
+//   cancelled              the picker was closed, or free text was ignored
+//   failed<TAB><error>     copying failed; rustoji also exits non-zero
+//
+// Tabs, newlines and backslashes in the value are escaped as \t, \n and \\. Other errors, before
+// anything was picked, exit non-zero without a line.
+fn print_result(args: &Args, status: &str, fields: &[&str]) {
+    if !args.print_result {
+        return;
+    }
+    let mut line = status.to_string();
+    for field in fields {
+        let field = field
+            .replace('\\', "\\\\")
+            .replace('\t', "\\t")
+            .replace('\n', "\\n");
+        line = format!("{line}\t{field}");
+    }
+    println!("{line}");
 }
 
-fn copy_emoji_to_clipboard(
-    emoji: &str,
-    expanded_png_emojis_path: &PathBuf,
-    copy_png_emoji_path: bool,
-) -> io::Result<ExitStatus> {
-    if !emoji.ends_with(".png") {
-        let cmd = Command::new("wl-copy")
-            .args(&[emoji, "-t", "text/plain"])
-            .status()?;
-        return Ok(cmd);
+// The kind and value fields of a copied or printed --print-result line.
+fn result_fields(selection: &Selection, expanded_png_emojis_path: &Path) -> [String; 2] {
+    match selection {
+        Selection::Unicode { glyph, .. } => ["unicode".to_string(), glyph.clone()],
+        Selection::Png(file_name) => [
+            "png".to_string(),
+            expanded_png_emojis_path
+                .join(file_name)
+                .display()
+                .to_string(),
+        ],
+        Selection::Text(text) => ["text".to_string(), text.clone()],
     }
+}
 
-    let emoji_path = expanded_png_emojis_path.join(emoji);
-    if copy_png_emoji_path {
-        let f = "file://".to_owned() + emoji_path.to_str().unwrap();
-        let cmd = Command::new("wl-copy")
-            .args(&[&f, "-t", "text/uri-list"])
-            .status()?;
-        return Ok(cmd);
+fn copy_and_notify(
+    args: &Args,
+    selection: &Selection,
+    fallback: Option<&str>,
+    expanded_png_emojis_path: &Path,
+) -> io::Result<()> {
+    let text = clipboard_text(selection, fallback, expanded_png_emojis_path, args);
+    let copied_text = text.as_ref().map(|(text, mime)| (text.as_str(), *mime));
+    let status_code =
+        match copy_emoji_to_clipboard(selection, copied_text, expanded_png_emojis_path, args) {
+            Ok(status_code) => status_code,
+            Err(err) => {
+                notify(args, None, &format!("Failed to copy: {err}"));
+                return Err(err);
+            }
+        };
+    if !status_code.success() {
+        let msg = format!(
+            "Failed to copy ({status_code}); is wl-copy installed and running under Wayland?"
+        );
+        notify(args, None, &msg);
+        return Err(io::Error::other(msg));
+    }
+    let emoji = selection.text();
+    let icon = match selection {
+        Selection::Png(file_name) => Some(expanded_png_emojis_path.join(file_name)),
+        _ => None,
+    };
+    if args.verbose {
+        notify(
+            args,
+            icon.as_deref(),
+            &format!("Copied {emoji} ({status_code})"),
+        );
+    } else {
+        notify(args, icon.as_deref(), &format!("Copied {emoji}"));
     }
 
-    let mut file = fs::File::open(emoji_path)?;
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer)?;
+    if let Some(post_copy_cmd) = &args.post_copy_cmd {
+        let glyph = match copied_text {
+            Some((text, _)) => text.to_string(),
+            None => expanded_png_emojis_path
+                .join(selection.text())
+                .to_string_lossy()
+                .into_owned(),
+        };
+        run_post_copy_cmd(post_copy_cmd, &glyph);
+    }
 
-    let mut child = Command::new("wl-copy")
-        .args(&["-t", "image/png"])
-        .stdin(Stdio::piped())
-        .spawn()?;
-    if let Some(mut stdin) = child.stdin.take() {
-        stdin.write_all(&buffer)?;
+    if let Some(sound) = &args.sound {
+        play_sound(&expand_path(sound)?);
     }
 
-    let status = child.wait()?;
-    Ok(status)
+    // copied first so it's on the clipboard even when typing doesn't reach the app
+    if let Some((text, _)) = copied_text.filter(|_| args.copy_and_type) {
+        type_text(text);
+    }
+    Ok(())
 }
 
-fn parse_args() -> (String, bool) {
-    let args: Vec<String> = env::args().collect();
+// Types the text into the focused window with wtype, or ydotool where wtype isn't installed.
+// Typing is a convenience on top of the copy, so a failure is only noted.
+fn type_text(text: &str) {
+    log::debug!("typing {text:?}");
+    let mut result = Command::new("wtype").args(["--", text]).status();
+    if matches!(&result, Err(err) if err.kind() == io::ErrorKind::NotFound) {
+        result = Command::new("ydotool").args(["type", "--", text]).status();
+    }
+    match result {
+        Ok(status) if status.success() => {}
+        Ok(status) => log::note!("Failed to type {text} ({status})"),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            log::note!("Failed to type {text}: neither wtype nor ydotool is installed")
+        }
+        Err(err) => log::note!("Failed to type {text}: {err}"),
+    }
+}
 
-    let copy_png_emoji_path = args // copy image's path instead of copying the actual image
-        .get(2)
-        .map_or(true, |arg| arg.to_lowercase() != "false");
+// For clipboard managers that miss wl-copy's copies, e.g. `cliphist store`. What was copied (or a
+// png emoji's path) replaces {glyph} in the arguments and is also written to the program's stdin.
+// A failure only gets a note, since the emoji is on the clipboard by then.
+fn run_post_copy_cmd(post_copy_cmd: &str, glyph: &str) {
+    let mut words = post_copy_cmd
+        .split_whitespace()
+        .map(|word| word.replace("{glyph}", glyph));
+    let Some(program) = words.next() else {
+        return;
+    };
+    log::debug!("running the post-copy command {program}");
+    match pipe_to(Command::new(&program).args(words), glyph.as_bytes()) {
+        Ok(status) if status.success() => {}
+        Ok(status) => log::note!("{program} failed ({status})"),
+        Err(err) => log::note!("Failed to run {program}: {err}"),
+    }
+}
 
-    let picker = args
-        .get(1)
-        .filter(|picker| SUPPORTED_PICKERS.contains(&picker.as_str()))
-        .map(|picker| picker.as_str())
-        .unwrap_or(SUPPORTED_PICKERS[0]);
+// A toned emoji shares its base emoji's fallback, 👍🏽 copies with (y) like 👍.
+fn ascii_fallback<'a>(details: &'a EmojiDetails, name: &str) -> Option<&'a str> {
+    let base_name = name
+        .rsplit_once(": ")
+        .map_or(name, |(base_name, _)| base_name);
+    details
+        .fallbacks
+        .get(name)
+        .or_else(|| details.fallbacks.get(base_name))
+        .map(String::as_str)
+}
 
-    (picker.to_string(), copy_png_emoji_path)
+// The emoji is named like in history (a name, an alias or a png file name) or given as its
+// glyph. Its entry stays, with its last use, but drops to the end of the history section.
+fn reset_count(
+    history: &mut history::History,
+    history_file_path: &Path,
+    unicode_emojis: &HashMap<String, String>,
+    aliases: &HashMap<String, String>,
+    emoji: &str,
+    compact: bool,
+) -> io::Result<()> {
+    let key = canonical_name(aliases, emoji);
+    let key = if history.contains_key(&key) {
+        key
+    } else {
+        let mut by_glyph = history
+            .keys()
+            .filter(|name| tones::resolve_glyph(unicode_emojis, name).as_deref() == Some(emoji));
+        match by_glyph.next() {
+            Some(name) => name.clone(),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{emoji} isn't in history"),
+                ))
+            }
+        }
+    };
+    if let Some(entry) = history.get_mut(&key) {
+        entry.count = 0;
+    }
+    history::save(history_file_path, history, compact)?;
+    println!("{key}: count 0");
+    Ok(())
 }
 
-fn ensure_folder_exists(folder: &Path) -> Result<()> {
-    if !folder.exists() {
-        fs::create_dir_all(folder)?;
+// Png emojis deleted from the folder are skipped when listing history, but their entries stay in
+// history.json until pruned. Unicode entries are never touched.
+fn prune_history(
+    history: &mut history::History,
+    history_file_path: &Path,
+    expanded_png_emojis_path: &Path,
+    compact: bool,
+) -> io::Result<()> {
+    let before = history.len();
+    history.retain(|key, _| {
+        !has_png_extension(Path::new(key)) || expanded_png_emojis_path.join(key).is_file()
+    });
+    let removed = before - history.len();
+    if removed > 0 {
+        history::save(history_file_path, history, compact)?;
     }
+    println!(
+        "Removed {removed} missing png emoji{} from history",
+        if removed == 1 { "" } else { "s" }
+    );
     Ok(())
 }
 
-fn load_json_or_default<T: serde::de::DeserializeOwned>(path: &Path) -> io::Result<T> {
-    if path.exists() {
-        let file_content = fs::read_to_string(path)?;
-        let parsed_data: T = serde_json::from_str(&file_content)?;
-        Ok(parsed_data)
-    } else {
-        Ok(serde_json::from_str("{}").unwrap())
+// With single_instance, a second launch (a hotkey pressed twice) exits while the first one's
+// picker is still open. flock is released by the kernel however rustoji exits, so a crash never
+// leaves a stale lock; the PID in the file is only there to say who holds it.
+fn lock_instance(lock_file_path: &Path) -> io::Result<Option<fs::File>> {
+    use std::os::fd::AsRawFd;
+
+    let mut lock_file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(lock_file_path)?;
+    // SAFETY: lock_file owns the fd and stays open while the lock is held; the lock goes with it
+    if unsafe { libc::flock(lock_file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::WouldBlock {
+            return Err(err);
+        }
+        let mut pid = String::new();
+        lock_file.read_to_string(&mut pid)?;
+        log::debug!("rustoji is already running (pid {}), exiting", pid.trim());
+        return Ok(None);
     }
+    lock_file.set_len(0)?;
+    writeln!(lock_file, "{}", std::process::id())?;
+    Ok(Some(lock_file))
 }
 
-fn fetch_unicode_emojis_file(path: &Path) -> io::Result<()> {
-    if UNICODE_EMOJIS_FILE_URL.is_empty() {
-        eprintln!("No URL provided for fetching the emojis file.");
+// With remember_picker, a picker named on the command line is written to last_picker and used by
+// later runs that don't name one, ahead of picker.default. RUSTOJI_PICKER still wins.
+fn remember_picker(
+    args: &mut Args,
+    config: &Config,
+    last_picker_file_path: &Path,
+) -> io::Result<()> {
+    if args.picker_given {
+        if let Mode::Pick = args.mode {
+            fs::write(last_picker_file_path, format!("{}\n", args.picker.name))?;
+        }
+        return Ok(());
+    }
+    if env::var_os("RUSTOJI_PICKER").is_some() {
         return Ok(());
     }
-    let status = Command::new("wget")
-        .args(&[UNICODE_EMOJIS_FILE_URL, "-O", path.to_str().unwrap()])
-        .status()?;
+    let last_picker = match fs::read_to_string(last_picker_file_path) {
+        Ok(last_picker) => last_picker,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    if let Some(picker) = picker::find(last_picker.trim()) {
+        log::debug!("using the last picker, {}", picker.name);
+        args.picker = picker;
+        args.picker_args = config
+            .strings(&format!("picker.{}.args", picker.name))?
+            .unwrap_or_default();
+    }
+    Ok(())
+}
+
+// Without a graphical session there's no clipboard to talk to; a --copy-cmd may still have one,
+// and --wayland or --x11 say there is one whatever the environment suggests.
+fn clipboard_available(args: &Args) -> bool {
+    args.copy_cmd.is_some() || display_server_for(args) != DisplayServer::Auto
+}
+
+// The display server copies go to: the one --wayland or --x11 forced, or else the session's.
+// XWayland sets DISPLAY too, so WAYLAND_DISPLAY comes first. Auto is left without a session.
+fn resolve_display_server(forced: DisplayServer, is_set: impl Fn(&str) -> bool) -> DisplayServer {
+    match forced {
+        DisplayServer::Auto if is_set("WAYLAND_DISPLAY") => DisplayServer::Wayland,
+        DisplayServer::Auto if is_set("DISPLAY") => DisplayServer::X11,
+        forced => forced,
+    }
+}
 
-    if !status.success() {
-        eprintln!("Failed to download the emojis file.");
+fn display_server_for(args: &Args) -> DisplayServer {
+    resolve_display_server(args.display_server, |name| env::var_os(name).is_some())
+}
+
+fn graphical_session() -> bool {
+    resolve_display_server(DisplayServer::Auto, |name| env::var_os(name).is_some())
+        != DisplayServer::Auto
+}
+
+// A forced display server fails up front when its clipboard tool is missing, rather than after
+// the pick.
+fn check_display_server(args: &Args) -> io::Result<()> {
+    let (flag, tool) = match args.display_server {
+        DisplayServer::Auto => return Ok(()),
+        DisplayServer::Wayland => ("--wayland", "wl-copy"),
+        DisplayServer::X11 => ("--x11", "xclip"),
+    };
+    if args.copy_cmd.is_none() && doctor::find_in_path(tool).is_none() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{flag} copies with {tool}, which isn't installed"),
+        ));
     }
     Ok(())
 }
 
-fn collect_png_emojis_and_filter(
-    path: &Path,
-    emojis_to_filter_out: &Vec<&String>,
-) -> io::Result<Vec<PathBuf>> {
-    let mut all_png_emojis = Vec::new();
-    if path.exists() {
-        for entry in fs::read_dir(path)? {
-            let entry = entry?;
-            if emojis_to_filter_out.contains(&&entry.file_name().into_string().unwrap()) {
-                continue;
-            }
-            let path = entry.path();
-            if path.is_file() && path.extension() == Some(std::ffi::OsStr::new("png")) {
-                all_png_emojis.push(path);
-            }
-        }
+// A pick made through an alias is recorded under the emoji's own name, so aliases don't split
+// its history. Toned aliases keep their tone.
+fn canonical_name(aliases: &HashMap<String, String>, name: &str) -> String {
+    if let Some(canonical) = aliases.get(name) {
+        return canonical.clone();
+    }
+    let toned_alias = name
+        .rsplit_once(": ")
+        .and_then(|(base, tone)| Some((aliases.get(base)?, tone)));
+    match toned_alias {
+        Some((canonical, tone)) => format!("{canonical}: {tone}"),
+        None => name.to_string(),
     }
-    Ok(all_png_emojis)
 }
 
-fn run_picker(
-    picker: &str,
+// Turns the picker's output back into what was picked. `None` means there's nothing to copy.
+fn parse_selection(
+    output: &str,
+    args: &Args,
     unicode_emojis: &HashMap<String, String>,
-    png_emojis: &Vec<PathBuf>,
-    sorted_history: &Vec<&String>,
-    expanded_png_emojis_path: &PathBuf,
-) -> io::Result<String> {
-    let mut command = Command::new(picker);
+    png_names: &HashMap<String, String>,
+    expanded_png_emojis_path: &Path,
+) -> io::Result<Option<Selection>> {
+    // unicode entries always carry the separator, so a name ending in ".png" isn't mistaken for a file.
+    // Typed text may hold a tab too, so it's only an entry when the name is a known emoji's.
+    if let Some((first, rest)) = output.split_once(FIELD_SEPARATOR) {
+        // drop the keywords field, if any
+        let second = rest.split(FIELD_SEPARATOR).next().unwrap_or(rest);
+        let name = match args.layout {
+            Layout::GlyphFirst => second,
+            Layout::NameFirst => first,
+        };
+        // the picker only saw a one-line preview of multi-line snippets, so the glyph comes from
+        // the name
+        if let Some(glyph) = tones::resolve_glyph(unicode_emojis, name) {
+            return Ok(Some(Selection::Unicode {
+                glyph,
+                name: name.to_string(),
+            }));
+        }
+    }
+    if has_png_extension(Path::new(output)) && expanded_png_emojis_path.join(output).is_file() {
+        return Ok(Some(Selection::Png(output.to_string())));
+    }
+    // a png emoji listed under its names.json name
+    if let Some((file_name, _)) = png_names.iter().find(|&(_, name)| name == output) {
+        return Ok(Some(Selection::Png(file_name.clone())));
+    }
+
+    match args.free_text {
+        FreeText::CopyVerbatim => Ok(Some(Selection::Text(output.to_string()))),
+        FreeText::Reject => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Not an emoji: {output}"),
+        )),
+        FreeText::Ignore => Ok(None),
+    }
+}
 
-    if picker == "fuzzel" {
-        command.arg("--dmenu").arg("--counter");
+fn print_stats(
+    args: &Args,
+    sorted_history: &[&String],
+    history: &history::History,
+    unicode_emojis: &HashMap<String, String>,
+    copies: u64,
+) {
+    if sorted_history.is_empty() && copies == 0 {
+        println!("No emojis copied yet.");
+        return;
     }
 
-    let mut child = command
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .spawn()?;
+    let shown = args.limit.unwrap_or(usize::MAX);
+    for name in sorted_history.iter().take(shown) {
+        let entry = &history[*name];
+        let label = match tones::resolve_glyph(unicode_emojis, name) {
+            Some(glyph) => format!("{glyph} {name}"),
+            None => name.to_string(),
+        };
+        let last_used = match entry.last_used {
+            Some(last_used) => format!(
+                "{} ({})",
+                history::format_relative(last_used),
+                history::format_local_time(last_used)
+            ),
+            None => "-".to_string(),
+        };
+        println!("{:>6}  {label}  {last_used}", entry.count);
+    }
+    println!("{copies} copies in total");
+}
 
-    if let Some(stdin) = child.stdin.as_mut() {
-        for emoji in sorted_history {
-            if emoji.ends_with(".png") {
-                let emoji_path = expanded_png_emojis_path.join(emoji);
-                let to_write = format!("{}\0icon\x1f{}", emoji, emoji_path.to_str().unwrap());
-                writeln!(stdin, "{to_write}")?;
-            } else {
-                writeln!(stdin, "{} {emoji}", unicode_emojis.get(*emoji).unwrap())?;
+// The history --stats prints, as CSV for spreadsheets: name, glyph (or a png emoji's path), count
+// and last use, most used first. Last use is in local time and empty for entries recorded before
+// it was kept.
+fn export_stats(
+    path: &Path,
+    sorted_history: &[&String],
+    history: &history::History,
+    unicode_emojis: &HashMap<String, String>,
+    expanded_png_emojis_path: &Path,
+) -> io::Result<()> {
+    let mut csv = io::BufWriter::new(fs::File::create(path)?);
+    writeln!(csv, "name,glyph,count,last_used")?;
+    for name in sorted_history {
+        let entry = &history[*name];
+        let glyph = match tones::resolve_glyph(unicode_emojis, name) {
+            Some(glyph) => glyph,
+            None if has_png_extension(Path::new(name.as_str())) => {
+                expanded_png_emojis_path.join(name).display().to_string()
             }
-        }
+            None => String::new(),
+        };
+        let last_used = entry
+            .last_used
+            .map(history::format_local_time)
+            .unwrap_or_default();
+        writeln!(
+            csv,
+            "{},{},{},{last_used}",
+            csv_field(name),
+            csv_field(&glyph),
+            entry.count
+        )?;
+    }
+    csv.flush()?;
+    println!(
+        "Wrote {} emojis to {}",
+        sorted_history.len(),
+        path.display()
+    );
+    Ok(())
+}
 
-        for emoji in png_emojis {
-            let file_name = emoji.file_name().unwrap().to_str().unwrap();
-            let to_write = format!("{}\0icon\x1f{}", file_name, emoji.to_str().unwrap());
-            writeln!(stdin, "{to_write}")?
-        }
+// Quoted when it holds a comma, a quote or a line break, with quotes doubled (RFC 4180).
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
 
-        for (emoji, value) in unicode_emojis
-            .into_iter()
-            .filter(|(key, _)| !sorted_history.contains(&key))
-        {
-            writeln!(stdin, "{} {}", value, emoji)?;
+// Each group with the number of distinct emojis in it, sorted by name. Aliases of an emoji are
+// counted once.
+fn print_categories(
+    unicode_emojis: &HashMap<String, String>,
+    groups: &HashMap<String, String>,
+    png_emojis: &[PathBuf],
+    expanded_png_emojis_path: &Path,
+) {
+    let mut categories: BTreeMap<String, HashSet<&str>> = BTreeMap::new();
+    for (name, group) in groups {
+        if let Some(glyph) = unicode_emojis.get(name) {
+            categories.entry(group.clone()).or_default().insert(glyph);
+        }
+    }
+    for path in png_emojis {
+        let name = png_emoji_name(expanded_png_emojis_path, path);
+        if let Some(group) = png_emoji_group(&name) {
+            let path = path.to_str().unwrap_or_default();
+            categories.entry(group).or_default().insert(path);
         }
     }
 
-    let output = child.wait_with_output()?;
-    let output_str = String::from_utf8_lossy(&output.stdout).trim().to_string();
-    Ok(output_str)
+    if categories.is_empty() {
+        println!("No categories found.");
+    }
+    for (category, emojis) in categories {
+        println!("{:>6}  {category}", emojis.len());
+    }
+}
+
+// Png emojis aren't checked, they always render.
+fn render_check(unicode_emojis: &HashMap<String, String>, font: &str) -> Result<()> {
+    let coverage = fonts::coverage(font)?;
+    let mut missing: Vec<(&String, &String)> = unicode_emojis
+        .iter()
+        .filter(|(_, glyph)| !coverage.covers(glyph))
+        .collect();
+    missing.sort();
+
+    let mut stdout = io::stdout().lock();
+    for (name, glyph) in &missing {
+        writeln!(stdout, "{glyph}\t{name}")?;
+    }
+    eprintln!(
+        "{} of {} emojis have no glyph in {}",
+        missing.len(),
+        unicode_emojis.len(),
+        coverage.file.display()
+    );
+    Ok(())
+}
+
+// Names and glyphs cycle through a block of real emoji codepoints, so entries are built just like
+// those from emojis.json.
+fn add_synthetic_emojis(unicode_emojis: &mut HashMap<String, String>, count: usize) {
+    for i in 0..count {
+        let glyph = char::from_u32(0x1F300 + (i % 0x250) as u32).unwrap_or('?');
+        unicode_emojis.insert(format!("stress_{i:07}"), glyph.to_string());
+    }
+}
+
+fn print_bench(stages: &[(&str, Duration)]) {
+    for (stage, elapsed) in stages {
+        println!("{stage:<28}{elapsed:>10.2?}");
+    }
+}
+
+// One emoji per line as "kind<TAB>name<TAB>glyph or path", or a JSON array with --json. JSON
+// entries carry a "group": the dataset's group for unicode emojis, the subfolder for png ones,
+// null when unknown.
+fn print_emoji_list(
+    args: &Args,
+    unicode_emojis: &HashMap<String, String>,
+    groups: &HashMap<String, String>,
+    png_emojis: &[PathBuf],
+    expanded_png_emojis_path: &Path,
+) -> io::Result<()> {
+    let mut unicode_emojis: Vec<(&String, &String)> = unicode_emojis.iter().collect();
+    unicode_emojis.sort();
+
+    let mut stdout = io::stdout().lock();
+    if args.json {
+        let mut list: Vec<serde_json::Value> = unicode_emojis
+            .iter()
+            .map(|&(name, glyph)| {
+                let group = groups.get(name);
+                serde_json::json!({ "kind": "unicode", "name": name, "glyph": glyph, "group": group })
+            })
+            .collect();
+        list.extend(png_emojis.iter().map(|path| {
+            let name = png_emoji_name(expanded_png_emojis_path, path);
+            let group = png_emoji_group(&name);
+            serde_json::json!({ "kind": "png", "name": name, "path": path, "group": group })
+        }));
+        serde_json::to_writer_pretty(&mut stdout, &list)?;
+        return writeln!(stdout);
+    }
+
+    for (name, glyph) in unicode_emojis {
+        writeln!(stdout, "unicode\t{name}\t{glyph}")?;
+    }
+    for path in png_emojis {
+        let name = png_emoji_name(expanded_png_emojis_path, path);
+        writeln!(stdout, "png\t{name}\t{}", path.display())?;
+    }
+    Ok(())
+}
+
+extern "C" fn on_interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+// Ctrl+C in a terminal reaches both rustoji and a terminal picker. Catching it lets the picker
+// restore the terminal and exit on its own, after which rustoji stops without touching history.
+// Unlike SIG_IGN, a handler is reset on exec, so the picker still sees the default disposition.
+fn catch_interrupts() {
+    let handler: extern "C" fn(libc::c_int) = on_interrupt;
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+    unsafe {
+        libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+    }
+}
+
+fn notify(args: &Args, icon: Option<&Path>, msg: &str) {
+    if args.quiet {
+        return;
+    }
+    let mut command = Command::new(&args.notifier);
+    if args.notifier == "dunstify" {
+        command.args(["-r", NOTIFICATION_ID]);
+    }
+    if let Some(icon) = icon {
+        command.arg("-i").arg(icon);
+    }
+
+    // a missing or broken notifier shouldn't fail the copy; nor should a nul byte, which no
+    // argument can hold
+    command.args([&msg.replace('\0', " "), "-t", "1000"]);
+    log::debug!("notifying: {command:?}");
+    if let Err(err) = command.status() {
+        log::note!("Failed to run {}: {err}", args.notifier);
+    }
+}
+
+// Plays the sound in the background with paplay, or pw-play where PulseAudio's tools aren't
+// installed. Like notify, failing to play is only reported.
+fn play_sound(sound: &Path) {
+    let mut result = Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "neither paplay nor pw-play is installed",
+    ));
+    for player in ["paplay", "pw-play"] {
+        match Command::new(player).arg(sound).stdin(Stdio::null()).spawn() {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            spawned => {
+                result = spawned;
+                break;
+            }
+        }
+    }
+    if let Err(err) = result {
+        log::note!("Failed to play {}: {err}", sound.display());
+    }
+}
+
+// Without xdg-open, or when it can't start, the path is printed so the folder can still be opened
+// by hand.
+fn open_folder(folder: &Path) {
+    log::debug!("opening {}", folder.display());
+    if let Err(err) = Command::new("xdg-open").arg(folder).spawn() {
+        log::note!("Failed to open {} with xdg-open: {err}", folder.display());
+        println!("{}", folder.display());
+    }
+}
+
+// The text a pick puts on the clipboard and its type, with --prefix, --repeat, --with-name and the
+// rest applied. `None` for a png emoji, which is copied as its path or the image.
+fn clipboard_text(
+    selection: &Selection,
+    fallback: Option<&str>,
+    expanded_png_emojis_path: &Path,
+    args: &Args,
+) -> Option<(String, &'static str)> {
+    // free text has no name and is copied as it is
+    if let Some(name) = selection.display_name().filter(|_| args.copy_name) {
+        let text = if args.newline {
+            format!("{name}\n")
+        } else {
+            name
+        };
+        return Some((text, "text/plain"));
+    }
+
+    // "😀 :) grinning" with both --with-fallback and --with-name
+    let mut name_suffix = match fallback {
+        Some(fallback) => format!(" {fallback}"),
+        None => String::new(),
+    };
+    if let Some(name) = selection.display_name().filter(|_| args.with_name) {
+        name_suffix = format!("{name_suffix} {name}");
+    }
+
+    // wl-copy offers a single type per invocation, so html replaces the plain copy
+    if args.html {
+        let html = match selection {
+            Selection::Png(file_name) => {
+                let emoji_path = expanded_png_emojis_path.join(file_name);
+                format!(
+                    "<img src=\"file://{}\" alt=\"{}\">{}",
+                    escape_html(&emoji_path.to_string_lossy()),
+                    escape_html(file_name),
+                    escape_html(&name_suffix)
+                )
+            }
+            _ => format!(
+                "<span>{}{}{}{}</span>",
+                escape_html(&args.prefix),
+                escape_html(&selection.text().repeat(args.repeat)),
+                escape_html(&args.suffix),
+                escape_html(&name_suffix)
+            ),
+        };
+        return Some((html, "text/html"));
+    }
+
+    // --repeat only applies to text, a png is copied once
+    if let Selection::Png(_) = selection {
+        return None;
+    }
+    let emoji = format!(
+        "{}{}{}",
+        args.prefix,
+        selection.text().repeat(args.repeat),
+        args.suffix
+    );
+    let text = if args.newline {
+        format!("{emoji}{name_suffix}\n")
+    } else {
+        format!("{emoji}{name_suffix}")
+    };
+    Some((text, "text/plain"))
+}
+
+// Copies `text` from clipboard_text, or else the png emoji the way --copy asks.
+fn copy_emoji_to_clipboard(
+    selection: &Selection,
+    text: Option<(&str, &str)>,
+    expanded_png_emojis_path: &Path,
+    args: &Args,
+) -> io::Result<ExitStatus> {
+    let file_name = match (text, selection) {
+        (Some((text, mime)), _) => return copy_data(args, text.as_bytes(), mime),
+        (None, Selection::Png(file_name)) => file_name,
+        (None, _) => return copy_data(args, selection.text().as_bytes(), "text/plain"),
+    };
+
+    let emoji_path = expanded_png_emojis_path.join(file_name);
+    if args.copy_mode == CopyMode::Path {
+        return copy_file_uri(&emoji_path, args);
+    }
+
+    let image_path = match args.image_size {
+        Some(size) => resized_png(&emoji_path, file_name, size, args),
+        None => emoji_path.clone(),
+    };
+    let mut file = fs::File::open(&image_path)?;
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer)?;
+
+    let copied = copy_data(args, &buffer, "image/png");
+    let failure = match &copied {
+        Ok(status) if status.success() => return copied,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return copied,
+        Ok(status) => status.to_string(),
+        Err(err) => err.to_string(),
+    };
+
+    // a file:// path is still pasteable in most places that would take the image; a tool that
+    // runs but rejects the copy is usually an older wl-copy or xclip without image/png support
+    let tool = copy_tool_name(args);
+    log::debug!("{tool} failed to copy image/png ({failure}), falling back to text/uri-list");
+    if args.verbose {
+        eprintln!(
+            "Copying {} as an image failed ({failure}); {tool} may not support image/png, copying its file:// path instead",
+            emoji_path.display()
+        );
+    }
+    copy_file_uri(&emoji_path, args)
+}
+
+// The program copy_data runs, for messages.
+fn copy_tool_name(args: &Args) -> String {
+    match &args.copy_cmd {
+        Some(copy_cmd) => copy_cmd.split_whitespace().next().unwrap_or("").to_string(),
+        None if display_server_for(args) == DisplayServer::X11 => "xclip".to_string(),
+        None => "wl-copy".to_string(),
+    }
+}
+
+// A copy of the png scaled to fit in size×size pixels, kept under resized/<size>/ in the data
+// folder and made again once the original is newer. ImageMagick does the scaling; without it, or
+// when it fails, the original is copied as it is.
+fn resized_png(emoji_path: &Path, file_name: &str, size: usize, args: &Args) -> PathBuf {
+    let resize = || -> io::Result<PathBuf> {
+        let resized_path = expand_path(&args.data_dir)?
+            .join("resized")
+            .join(size.to_string())
+            .join(file_name);
+        let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified());
+        if let (Ok(resized), Ok(original)) = (modified(&resized_path), modified(emoji_path)) {
+            if resized >= original {
+                return Ok(resized_path);
+            }
+        }
+        if let Some(parent) = resized_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // written aside first, so a failed resize never passes for a finished one
+        let partial_path = resized_path.with_extension("png.partial");
+        log::debug!("resizing {}", emoji_path.display());
+        let status = imagemagick(&[
+            emoji_path.as_os_str(),
+            "-resize".as_ref(),
+            format!("{size}x{size}").as_ref(),
+            format!("png:{}", partial_path.display()).as_ref(),
+        ])?;
+        if !status.success() {
+            let _ = fs::remove_file(&partial_path);
+            return Err(io::Error::other(format!("ImageMagick failed ({status})")));
+        }
+        fs::rename(&partial_path, &resized_path)?;
+        Ok(resized_path)
+    };
+    match resize() {
+        Ok(resized_path) => resized_path,
+        Err(err) => {
+            log::note!(
+                "Failed to resize {} to {size}px, copying it as it is: {err}",
+                emoji_path.display()
+            );
+            emoji_path.to_path_buf()
+        }
+    }
+}
+
+// Runs ImageMagick as magick, or as convert on ImageMagick 6, which only has that.
+fn imagemagick(arguments: &[&OsStr]) -> io::Result<ExitStatus> {
+    let run = |program: &str| Command::new(program).args(arguments).status();
+    match run("magick") {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => run("convert"),
+        status => status,
+    }
+    .map_err(|err| match err.kind() {
+        io::ErrorKind::NotFound => io::Error::new(err.kind(), "ImageMagick isn't installed"),
+        _ => err,
+    })
+}
+
+fn copy_file_uri(path: &Path, args: &Args) -> io::Result<ExitStatus> {
+    let uri = "file://".to_owned() + path.to_str().unwrap();
+    copy_data(args, uri.as_bytes(), "text/uri-list")
+}
+
+// The wl-copy (or xclip) commands copy_data runs, one per selection, each with whether `data`
+// goes to its stdin rather than in its arguments.
+fn clipboard_commands(args: &Args, data: &[u8], mime: &str) -> Vec<(Command, bool)> {
+    let primaries: &[bool] = match args.selection {
+        ClipboardSelection::Clipboard => &[false],
+        ClipboardSelection::Primary => &[true],
+        ClipboardSelection::Both => &[false, true],
+    };
+    primaries
+        .iter()
+        .map(|&primary| {
+            if display_server_for(args) == DisplayServer::X11 {
+                let selection = if primary { "primary" } else { "clipboard" };
+                let mut command = Command::new("xclip");
+                command.args(["-selection", selection, "-t", mime]);
+                return (command, true);
+            }
+            let mut command = wl_copy(args);
+            if primary {
+                command.arg("--primary");
+            }
+            command.args(["-t", mime]);
+            // an argument can't hold a nul byte either
+            if mime == "image/png" || data.contains(&0) {
+                return (command, true);
+            }
+            // after --, text starting with a dash (the kaomoji -_-, a "-- " signature) isn't
+            // taken for an option
+            command.arg("--").arg(&*String::from_utf8_lossy(data));
+            (command, false)
+        })
+        .collect()
+}
+
+// Puts `data` of type `mime` on the clipboard. wl-copy takes text as an argument and images on
+// stdin; on X11, xclip gets everything on stdin. A --copy-cmd program always gets the data
+// on stdin, with {mime} in its arguments replaced by the type. --selection both runs wl-copy (or
+// xclip) once per selection; a --copy-cmd picks its own selection in its arguments.
+fn copy_data(args: &Args, data: &[u8], mime: &str) -> io::Result<ExitStatus> {
+    log::debug!("copying {} bytes as {mime}", data.len());
+    let Some(copy_cmd) = &args.copy_cmd else {
+        let mut status = None;
+        for (mut command, on_stdin) in clipboard_commands(args, data, mime) {
+            let copied = if on_stdin {
+                pipe_to(&mut command, data)
+            } else {
+                command.status()
+            }
+            .map_err(clipboard_error)?;
+            if !copied.success() {
+                return Ok(copied);
+            }
+            status = Some(copied);
+        }
+        return Ok(status.expect("at least one selection"));
+    };
+
+    let mut words = copy_cmd
+        .split_whitespace()
+        .map(|word| word.replace("{mime}", mime));
+    let program = words
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "--copy-cmd is empty"))?;
+    pipe_to(Command::new(&program).args(words), data).map_err(|err| {
+        if err.kind() == io::ErrorKind::NotFound {
+            io::Error::new(err.kind(), format!("{program} not found"))
+        } else {
+            err
+        }
+    })
+}
+
+fn pipe_to(command: &mut Command, data: &[u8]) -> io::Result<ExitStatus> {
+    let mut child = command.stdin(Stdio::piped()).spawn()?;
+    let written = match child.stdin.take() {
+        Some(mut stdin) => stdin.write_all(data),
+        None => Ok(()),
+    };
+    let status = child.wait()?;
+    // a write error means the program gave up early; a failing exit status already says why
+    match written {
+        Err(err) if status.success() => Err(err),
+        _ => Ok(status),
+    }
+}
+
+fn wl_copy(args: &Args) -> Command {
+    let mut command = Command::new("wl-copy");
+    if let Some(seat) = &args.seat {
+        command.args(["--seat", seat]);
+    }
+    command
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn clipboard_error(err: io::Error) -> io::Error {
+    if err.kind() == io::ErrorKind::NotFound {
+        return io::Error::new(
+            io::ErrorKind::NotFound,
+            "wl-copy (or xclip on X11) not found; is wl-clipboard installed?",
+        );
+    }
+    err
+}
+
+// Prints the settings as parse_args resolved them, flags over RUSTOJI_* variables over the config
+// file over defaults, in the config file's own format. Unset options are commented out.
+fn show_config(args: &Args) {
+    let string = |value: &str| Some(Value::String(value.to_string()));
+    let boolean = |value: bool| Some(Value::Boolean(value));
+    let number = |value: Option<usize>| value.map(|value| Value::Integer(value as i64));
+    // in the largest unit that divides it evenly, as parse_duration reads it
+    let duration = |value: Option<Duration>| {
+        value.map(|value| {
+            let seconds = value.as_secs();
+            let (per_unit, unit) = [
+                (7 * 24 * 60 * 60, "w"),
+                (24 * 60 * 60, "d"),
+                (60 * 60, "h"),
+                (60, "m"),
+            ]
+            .into_iter()
+            .find(|&(per_unit, _)| seconds > 0 && seconds % per_unit == 0)
+            .unwrap_or((1, "s"));
+            Value::String(format!("{}{unit}", seconds / per_unit))
+        })
+    };
+    // --width and --lines are kept as the text passed to the picker
+    let integer = |value: &Option<String>| {
+        value.as_deref().map(|value| match value.parse() {
+            Ok(number) => Value::Integer(number),
+            Err(_) => Value::String(value.to_string()),
+        })
+    };
+    let settings = [
+        ("data_dir", string(&args.data_dir)),
+        ("emojis_file_name", string(&args.emojis_file_name)),
+        ("history_file_name", string(&args.history_file_name)),
+        ("png_dir", string(&args.png_dir)),
+        ("profile", args.profile.as_deref().and_then(string)),
+        (
+            "emojis",
+            Some(Value::Array(
+                args.extra_emojis_files
+                    .iter()
+                    .map(|file| Value::String(file.clone()))
+                    .collect(),
+            )),
+        ),
+        (
+            "copy_mode",
+            string(match args.copy_mode {
+                CopyMode::Path => "path",
+                CopyMode::Image => "image",
+            }),
+        ),
+        ("image_size", number(args.image_size)),
+        ("copy_cmd", args.copy_cmd.as_deref().and_then(string)),
+        (
+            "post_copy_cmd",
+            args.post_copy_cmd.as_deref().and_then(string),
+        ),
+        ("copy_and_type", boolean(args.copy_and_type)),
+        (
+            "selection",
+            string(match args.selection {
+                ClipboardSelection::Clipboard => "clipboard",
+                ClipboardSelection::Primary => "primary",
+                ClipboardSelection::Both => "both",
+            }),
+        ),
+        (
+            "display_server",
+            string(match args.display_server {
+                DisplayServer::Auto => "auto",
+                DisplayServer::Wayland => "wayland",
+                DisplayServer::X11 => "x11",
+            }),
+        ),
+        ("seat", args.seat.as_deref().and_then(string)),
+        ("html", boolean(args.html)),
+        ("newline", boolean(args.newline)),
+        ("with_name", boolean(args.with_name)),
+        ("with_fallback", boolean(args.with_fallback)),
+        ("prefix", string(&args.prefix)),
+        ("suffix", string(&args.suffix)),
+        (
+            "free_text",
+            string(match args.free_text {
+                FreeText::CopyVerbatim => "copy-verbatim",
+                FreeText::Reject => "reject",
+                FreeText::Ignore => "ignore",
+            }),
+        ),
+        ("notifier", string(&args.notifier)),
+        ("sound", args.sound.as_deref().and_then(string)),
+        ("no_history", boolean(args.no_history)),
+        ("compact_history", boolean(args.compact_history)),
+        ("cooldown", duration(args.cooldown)),
+        ("new_first", boolean(args.new_first)),
+        ("new_window", duration(Some(args.new_window))),
+        ("png_weight", Some(Value::Float(args.png_weight))),
+        ("unicode_weight", Some(Value::Float(args.unicode_weight))),
+        (
+            "select_history_boost",
+            Some(Value::Float(args.select_history_boost)),
+        ),
+        ("limit", number(args.limit)),
+        ("max_icons", number(args.max_icons)),
+        ("max_name_len", number(args.max_name_len)),
+        ("no_icons", boolean(args.no_icons)),
+        ("expand_tones", boolean(args.expand_tones)),
+        ("variants", boolean(args.variants)),
+        ("show_codepoints", boolean(args.show_codepoints)),
+        ("search_category", boolean(args.search_category)),
+        (
+            "search_field",
+            string(match args.search_field {
+                SearchField::Name => "name",
+                SearchField::Description => "description",
+                SearchField::Both => "both",
+            }),
+        ),
+        (
+            "sort_all",
+            string(match args.sort_all {
+                SortOrder::Name => "name",
+                SortOrder::Codepoint => "codepoint",
+            }),
+        ),
+        ("snippets", boolean(args.snippets)),
+        ("follow_symlinks", boolean(args.follow_symlinks)),
+        ("validate_images", boolean(args.validate_images)),
+        ("cache", boolean(args.cache)),
+        ("fetch_timeout", duration(args.fetch_timeout)),
+        ("builtin_picker", boolean(args.builtin_picker)),
+        ("remember_picker", boolean(args.remember_picker)),
+        ("single_instance", boolean(args.single_instance)),
+        ("font", string(&args.font)),
+        ("verbose", boolean(args.verbose)),
+        ("quiet", boolean(args.quiet)),
+    ];
+    let picker_settings = [
+        ("default", string(args.picker.name)),
+        ("prompt", args.prompt.as_deref().and_then(string)),
+        (
+            "layout",
+            string(match args.layout {
+                Layout::GlyphFirst => "glyph-first",
+                Layout::NameFirst => "name-first",
+            }),
+        ),
+        ("timeout", duration(args.picker_timeout)),
+        ("dpi_aware", args.dpi_aware.as_deref().and_then(string)),
+        ("width", integer(&args.width)),
+        ("lines", integer(&args.lines)),
+        ("anchor", args.anchor.as_deref().and_then(string)),
+    ];
+
+    let print = |key: &str, value: &Option<Value>| match value {
+        Some(value) => println!("{key} = {value}"),
+        None => println!("# {key} ="),
+    };
+    for (key, value) in &settings {
+        print(key, value);
+    }
+    println!();
+    println!("[picker]");
+    for (key, value) in &picker_settings {
+        print(key, value);
+    }
+    if !args.picker_env.is_empty() {
+        println!();
+        println!("[picker.env]");
+        for (key, value) in &args.picker_env {
+            println!("{key} = {}", Value::String(value.clone()));
+        }
+    }
+    if !args.picker_args.is_empty() {
+        let picker_args = args
+            .picker_args
+            .iter()
+            .cloned()
+            .map(Value::String)
+            .collect();
+        println!();
+        println!("[picker.{}]", args.picker.name);
+        println!("args = {}", Value::Array(picker_args));
+    }
+}
+
+fn parse_args(config: &Config) -> io::Result<Args> {
+    parse_args_from(config, env::args().skip(1), |name| env::var(name).ok())
+}
+
+// parse_args with the command line and environment variables given, so tests don't see the real
+// ones.
+fn parse_args_from(
+    config: &Config,
+    mut raw_args: impl Iterator<Item = String>,
+    env_var: impl Fn(&str) -> Option<String>,
+) -> io::Result<Args> {
+    let mut args = Args {
+        mode: Mode::Pick,
+        picker: &picker::PICKERS[0],
+        // copy_png_path = true/false came before copy_mode and is still read
+        copy_mode: match config.string("copy_mode")? {
+            Some(copy_mode) => parse_copy_mode(&copy_mode)?,
+            None => match config.boolean("copy_png_path")? {
+                Some(false) => CopyMode::Image,
+                _ => CopyMode::Path,
+            },
+        },
+        new_first: config.boolean("new_first")?.unwrap_or(false),
+        new_window: match config.string("new_window")? {
+            Some(window) => parse_duration(&window)?,
+            None => DEFAULT_NEW_WINDOW,
+        },
+        no_history: config.boolean("no_history")?.unwrap_or(false),
+        newline: config.boolean("newline")?.unwrap_or(false),
+        force: false,
+        dpi_aware: config.string("picker.dpi_aware")?,
+        expand_tones: config.boolean("expand_tones")?.unwrap_or(false),
+        limit: config.integer("limit")?.map(|limit| limit as usize),
+        notifier: config
+            .string("notifier")?
+            .unwrap_or_else(|| DEFAULT_NOTIFIER.to_string()),
+        verbose: config.boolean("verbose")?.unwrap_or(false),
+        json: false,
+        prompt: config.string("picker.prompt")?,
+        query: None,
+        snippets: config.boolean("snippets")?.unwrap_or(true),
+        free_text: match config.string("free_text")? {
+            Some(free_text) => parse_free_text(&free_text)?,
+            None => FreeText::CopyVerbatim,
+        },
+        html: config.boolean("html")?.unwrap_or(false),
+        follow_symlinks: config.boolean("follow_symlinks")?.unwrap_or(true),
+        validate_images: config.boolean("validate_images")?.unwrap_or(false),
+        no_icons: config.boolean("no_icons")?.unwrap_or(false),
+        extra_emojis_files: config.strings("emojis")?.unwrap_or_default(),
+        width: config
+            .integer("picker.width")?
+            .map(|width| width.to_string()),
+        lines: config
+            .integer("picker.lines")?
+            .map(|lines| lines.to_string()),
+        anchor: config.string("picker.anchor")?,
+        max_name_len: config.integer("max_name_len")?.map(|len| len as usize),
+        with_name: config.boolean("with_name")?.unwrap_or(false),
+        with_fallback: config.boolean("with_fallback")?.unwrap_or(false),
+        sound: config.string("sound")?,
+        data_dir: config
+            .string("data_dir")?
+            .unwrap_or_else(|| DATA_FOLDER.to_string()),
+        emojis_file_name: config
+            .string("emojis_file_name")?
+            .unwrap_or_else(|| "emojis.json".to_string()),
+        history_file_name: config
+            .string("history_file_name")?
+            .unwrap_or_else(|| "history.json".to_string()),
+        png_dir: config
+            .string("png_dir")?
+            .unwrap_or_else(|| PNG_EMOJIS_PATH.to_string()),
+        repeat: 1,
+        cache: config.boolean("cache")?.unwrap_or(false),
+        builtin_picker: config.boolean("builtin_picker")?.unwrap_or(false),
+        seat: config.string("seat")?,
+        display_server: match config.string("display_server")? {
+            Some(display_server) => parse_display_server(&display_server)?,
+            None => DisplayServer::Auto,
+        },
+        selection: match config.string("selection")? {
+            Some(selection) => parse_selection_target(&selection)?,
+            None => ClipboardSelection::Clipboard,
+        },
+        font: config
+            .string("font")?
+            .unwrap_or_else(|| "emoji".to_string()),
+        picker_env: config.string_table("picker.env")?,
+        copy_cmd: config.string("copy_cmd")?,
+        post_copy_cmd: config.string("post_copy_cmd")?,
+        since: None,
+        recents_only: false,
+        quiet: config.boolean("quiet")?.unwrap_or(false),
+        layout: match config.string("picker.layout")? {
+            Some(layout) => parse_layout(&layout)?,
+            None => Layout::GlyphFirst,
+        },
+        show_codepoints: config.boolean("show_codepoints")?.unwrap_or(false),
+        category_icons: config
+            .string_table("category_icons")?
+            .into_iter()
+            .map(|(group, icon)| Ok((group, expand_path(&icon)?.display().to_string())))
+            .collect::<io::Result<_>>()?,
+        fetch_timeout: config
+            .string("fetch_timeout")?
+            .map(|timeout| parse_duration(&timeout))
+            .transpose()?,
+        picker_timeout: config
+            .string("picker.timeout")?
+            .map(|timeout| parse_duration(&timeout))
+            .transpose()?,
+        select: None,
+        first: false,
+        remember_picker: config.boolean("remember_picker")?.unwrap_or(false),
+        picker_given: false,
+        copy_name: false,
+        no_record: false,
+        single_instance: config.boolean("single_instance")?.unwrap_or(false),
+        no_picker: false,
+        png_weight: config.float("png_weight")?.unwrap_or(1.0),
+        unicode_weight: config.float("unicode_weight")?.unwrap_or(1.0),
+        cooldown: config
+            .string("cooldown")?
+            .map(|cooldown| parse_duration(&cooldown))
+            .transpose()?,
+        variants: config.boolean("variants")?.unwrap_or(false),
+        compact_history: config.boolean("compact_history")?.unwrap_or(false),
+        sort_all: match config.string("sort_all")? {
+            Some(order) => parse_sort_order(&order)?,
+            None => SortOrder::Name,
+        },
+        search_field: match config.string("search_field")? {
+            Some(field) => parse_search_field(&field)?,
+            None => SearchField::Name,
+        },
+        select_history_boost: config.float("select_history_boost")?.unwrap_or(0.5),
+        stress: None,
+        prefix: config.string("prefix")?.unwrap_or_default(),
+        suffix: config.string("suffix")?.unwrap_or_default(),
+        copy_and_type: config.boolean("copy_and_type")?.unwrap_or(false),
+        profile: config.string("profile")?,
+        max_icons: config
+            .integer("max_icons")?
+            .map(|max_icons| max_icons as usize),
+        search_category: config.boolean("search_category")?.unwrap_or(false),
+        image_size: config.integer("image_size")?.map(|size| size as usize),
+        dmenu_passthrough: false,
+        selection_fd: None,
+        print_result: false,
+        picker_args: Vec::new(),
+    };
+
+    if let Some(picker) = config.string("picker.default")? {
+        if let Some(picker) = picker::find(&picker) {
+            args.picker = picker;
+        } else {
+            eprintln!("Unsupported picker in config: {picker}");
+        }
+    }
+
+    // RUSTOJI_PICKER sits between the config file and the command line
+    if let Some(picker) = env_var("RUSTOJI_PICKER") {
+        if let Some(picker) = picker::find(&picker) {
+            args.picker = picker;
+        } else {
+            eprintln!("Unsupported picker in RUSTOJI_PICKER: {picker}");
+        }
+    }
+    // and so does RUSTOJI_COPY_MODE, below --copy and the positional true/false
+    if let Some(copy_mode) = env_var("RUSTOJI_COPY_MODE") {
+        match parse_copy_mode(&copy_mode) {
+            Ok(copy_mode) => args.copy_mode = copy_mode,
+            Err(_) => {
+                log::note!("RUSTOJI_COPY_MODE must be path or image, not {copy_mode}; ignoring it")
+            }
+        }
+    }
+
+    let mut positional = Vec::new();
+    let mut png_dir_given = false;
+    let mut copy_mode_given = false;
+    while let Some(arg) = raw_args.next() {
+        match arg.as_str() {
+            "--new-first" => args.new_first = true,
+            "--no-history" => args.no_history = true,
+            "--newline" => args.newline = true,
+            "--force" => args.force = true,
+            "--stats" => args.mode = Mode::Stats,
+            "--list" => args.mode = Mode::List,
+            "--json" => args.json = true,
+            "--free-text" => args.free_text = parse_free_text(&flag_value(&mut raw_args, &arg)?)?,
+            "--prompt" => args.prompt = Some(flag_value(&mut raw_args, &arg)?),
+            "--query" => args.query = Some(flag_value(&mut raw_args, &arg)?),
+            "--verbose" | "-v" => args.verbose = true,
+            "--notifier" => args.notifier = flag_value(&mut raw_args, &arg)?,
+            "--expand-tones" => args.expand_tones = true,
+            "--limit" => args.limit = Some(parse_number(&flag_value(&mut raw_args, &arg)?)?),
+            "--dpi-aware" => args.dpi_aware = Some(flag_value(&mut raw_args, &arg)?),
+            "--new-window" => args.new_window = parse_duration(&flag_value(&mut raw_args, &arg)?)?,
+            "--html" => args.html = true,
+            "--no-follow-symlinks" => args.follow_symlinks = false,
+            "--bench-startup" => args.mode = Mode::Bench,
+            // undocumented: --bench-startup with this many made-up emojis added
+            "--stress" => {
+                args.mode = Mode::Bench;
+                args.stress = Some(parse_number(&flag_value(&mut raw_args, &arg)?)?);
+            }
+            "--categories" => args.mode = Mode::Categories,
+            "--validate-images" => args.validate_images = true,
+            "--no-icons" => args.no_icons = true,
+            "--emojis" => args
+                .extra_emojis_files
+                .push(flag_value(&mut raw_args, &arg)?),
+            "--max-name-len" => {
+                args.max_name_len = Some(parse_number(&flag_value(&mut raw_args, &arg)?)?)
+            }
+            "--with-name" => args.with_name = true,
+            "--with-fallback" => args.with_fallback = true,
+            "--sound" => args.sound = Some(flag_value(&mut raw_args, &arg)?),
+            // read by main before the config was loaded
+            "--config" => drop(flag_value(&mut raw_args, &arg)?),
+            "--data-dir" => args.data_dir = flag_value(&mut raw_args, &arg)?,
+            "--png-dir" => {
+                args.png_dir = flag_value(&mut raw_args, &arg)?;
+                png_dir_given = true;
+            }
+            "--list-pickers" => args.mode = Mode::Pickers,
+            "--open-config" => args.mode = Mode::OpenConfig,
+            "--open-data" => args.mode = Mode::OpenData,
+            "--repeat" => args.repeat = parse_repeat(&flag_value(&mut raw_args, &arg)?)?,
+            "--cache" => args.cache = true,
+            "--builtin-picker" => args.builtin_picker = true,
+            "--seat" => args.seat = Some(flag_value(&mut raw_args, &arg)?),
+            "--selection" => {
+                args.selection = parse_selection_target(&flag_value(&mut raw_args, &arg)?)?
+            }
+            "--primary" => args.selection = ClipboardSelection::Primary,
+            "--wayland" => args.display_server = DisplayServer::Wayland,
+            "--x11" => args.display_server = DisplayServer::X11,
+            "--render-check" => args.mode = Mode::RenderCheck,
+            "--font" => args.font = flag_value(&mut raw_args, &arg)?,
+            "--picker-env" => args
+                .picker_env
+                .push(parse_env(&flag_value(&mut raw_args, &arg)?)?),
+            "--copy-cmd" => args.copy_cmd = Some(flag_value(&mut raw_args, &arg)?),
+            "--post-copy-cmd" => args.post_copy_cmd = Some(flag_value(&mut raw_args, &arg)?),
+            "--since" => args.since = Some(parse_duration(&flag_value(&mut raw_args, &arg)?)?),
+            "--recents-only" => args.recents_only = true,
+            "--quiet" | "-q" => args.quiet = true,
+            "--layout" => args.layout = parse_layout(&flag_value(&mut raw_args, &arg)?)?,
+            "--show-codepoints" => args.show_codepoints = true,
+            "--fetch-timeout" => {
+                args.fetch_timeout = Some(parse_duration(&flag_value(&mut raw_args, &arg)?)?)
+            }
+            "--picker-timeout" => {
+                args.picker_timeout = Some(parse_duration(&flag_value(&mut raw_args, &arg)?)?)
+            }
+            "--select" => args.select = Some(flag_value(&mut raw_args, &arg)?),
+            "--first" => args.first = true,
+            "--copy-name" => args.copy_name = true,
+            "--no-record" => args.no_record = true,
+            "--single-instance" => args.single_instance = true,
+            "--no-picker" => args.no_picker = true,
+            "--cooldown" => {
+                args.cooldown = Some(parse_duration(&flag_value(&mut raw_args, &arg)?)?)
+            }
+            "--variants" => args.variants = true,
+            "--compact-history" => args.compact_history = true,
+            "--sort-all" => args.sort_all = parse_sort_order(&flag_value(&mut raw_args, &arg)?)?,
+            "--search-field" => {
+                args.search_field = parse_search_field(&flag_value(&mut raw_args, &arg)?)?
+            }
+            "--png-weight" => args.png_weight = parse_weight(&flag_value(&mut raw_args, &arg)?)?,
+            "--unicode-weight" => {
+                args.unicode_weight = parse_weight(&flag_value(&mut raw_args, &arg)?)?
+            }
+            "--db-info" => args.mode = Mode::DbInfo,
+            "--prune-history" => args.mode = Mode::PruneHistory,
+            "--select-history-boost" => {
+                args.select_history_boost = parse_weight(&flag_value(&mut raw_args, &arg)?)?
+            }
+            "--prefix" => args.prefix = flag_value(&mut raw_args, &arg)?,
+            "--suffix" => args.suffix = flag_value(&mut raw_args, &arg)?,
+            "--copy-and-type" => args.copy_and_type = true,
+            "--profile" => args.profile = Some(flag_value(&mut raw_args, &arg)?),
+            "--reset-count" => args.mode = Mode::ResetCount(flag_value(&mut raw_args, &arg)?),
+            "--max-icons" => {
+                args.max_icons = Some(parse_number(&flag_value(&mut raw_args, &arg)?)?)
+            }
+            "--show-config" => args.mode = Mode::ShowConfig,
+            "--search-category" => args.search_category = true,
+            "--image-size" => {
+                args.image_size = Some(parse_number(&flag_value(&mut raw_args, &arg)?)?)
+            }
+            "--export-stats" => args.mode = Mode::ExportStats(flag_value(&mut raw_args, &arg)?),
+            "--dmenu-passthrough" => args.dmenu_passthrough = true,
+            "--selection-fd" => {
+                let fd = parse_number(&flag_value(&mut raw_args, &arg)?)?;
+                args.selection_fd = Some(i32::try_from(fd).map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Invalid file descriptor: {fd}"),
+                    )
+                })?);
+            }
+            "--print-result" => args.print_result = true,
+            "--copy" => {
+                args.copy_mode = parse_copy_mode(&flag_value(&mut raw_args, &arg)?)?;
+                copy_mode_given = true;
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    // --quiet wins, whichever of the two came from the config
+    if args.quiet {
+        args.verbose = false;
+    }
+
+    if let Some(dpi_aware) = &args.dpi_aware {
+        if !["auto", "yes", "no"].contains(&dpi_aware.as_str()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("--dpi-aware must be auto, yes or no, not {dpi_aware}"),
+            ));
+        }
+    }
+
+    if positional.first().is_some_and(|arg| arg == "init") {
+        args.mode = Mode::Init;
+        positional.remove(0);
+    }
+
+    if positional.first().is_some_and(|arg| arg == "daemon") {
+        positional.remove(0);
+        args.mode = if positional.first().is_some_and(|arg| arg == "stop") {
+            positional.remove(0);
+            Mode::StopDaemon
+        } else {
+            Mode::Daemon
+        };
+    }
+
+    if positional.first().is_some_and(|arg| arg == "show") {
+        args.mode = Mode::Show;
+        positional.remove(0);
+    }
+
+    if positional.first().is_some_and(|arg| arg == "import-pack") {
+        positional.remove(0);
+        if positional.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "import-pack needs the folder to import",
+            ));
+        }
+        args.mode = Mode::ImportPack(positional.remove(0));
+    }
+
+    if positional.first().is_some_and(|arg| arg == "add-emoji") {
+        positional.remove(0);
+        if positional.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "add-emoji needs the url of a png image",
+            ));
+        }
+        let url = positional.remove(0);
+        let name = (!positional.is_empty()).then(|| positional.remove(0));
+        args.mode = Mode::AddEmoji(url, name);
+    }
+
+    if positional.first().is_some_and(|picker| picker == "none") {
+        args.no_picker = true;
+    } else if let Some(picker) = positional.first().and_then(|picker| picker::find(picker)) {
+        args.picker = picker;
+        args.picker_given = true;
+    }
+
+    if let Some(arg) = positional.get(1) {
+        let (copy_mode, name) = if arg.to_lowercase() == "false" {
+            (CopyMode::Image, "image")
+        } else {
+            (CopyMode::Path, "path")
+        };
+        log::note!(
+            "The true/false argument after the picker is deprecated, use --copy {name} instead"
+        );
+        if !copy_mode_given {
+            args.copy_mode = copy_mode;
+        }
+    }
+
+    // both close stdout once the entries are written, so the selector sees the end of its input
+    if args.print_result && (args.dmenu_passthrough || args.selection_fd.is_some()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--print-result can't be used with --dmenu-passthrough or --selection-fd, which close stdout after the entries",
+        ));
+    }
+
+    // the markup is for pasting, typing it would put the tags in the text
+    if args.copy_and_type && args.html {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--copy-and-type can't be used with --html, which copies markup",
+        ));
+    }
+
+    // a profile keeps its own emojis.json, history and other data files in a subfolder of the
+    // data folder, and may have its own png emojis folder
+    if let Some(profile) = &args.profile {
+        if profile.is_empty() || profile.contains('/') || profile.starts_with('.') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{profile:?} can't be a profile name, it has to be a plain folder name"),
+            ));
+        }
+        args.data_dir = format!("{}/profiles/{profile}", args.data_dir.trim_end_matches('/'));
+        if !png_dir_given {
+            if let Some(png_dir) = config.string(&format!("profiles.{profile}.png_dir"))? {
+                args.png_dir = png_dir;
+            }
+        }
+    }
+
+    args.picker_args = config
+        .strings(&format!("picker.{}.args", args.picker.name))?
+        .unwrap_or_default();
+
+    Ok(args)
+}
+
+// The value following `flag` on the command line, for the few flags needed before parse_args.
+fn early_flag_value(flag: &str) -> Option<String> {
+    let mut raw_args = env::args().skip(1);
+    raw_args.find(|arg| arg == flag)?;
+    raw_args.next()
+}
+
+// Expands a leading `~`. expanduser falls back to the passwd entry when HOME is unset, so this
+// only fails when neither knows a home directory, e.g. in some sandboxes.
+fn expand_path(path: &str) -> io::Result<PathBuf> {
+    expanduser(path).map_err(|err| {
+        let hint = if env::var_os("HOME").is_none() {
+            "; set HOME, or give every path explicitly with --config, --data-dir and --png-dir"
+        } else {
+            ""
+        };
+        io::Error::new(err.kind(), format!("Can't expand {path}: {err}{hint}"))
+    })
+}
+
+fn flag_value(raw_args: &mut impl Iterator<Item = String>, flag: &str) -> io::Result<String> {
+    raw_args.next().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{flag} requires a value"),
+        )
+    })
+}
+
+fn parse_free_text(value: &str) -> io::Result<FreeText> {
+    match value {
+        "copy-verbatim" => Ok(FreeText::CopyVerbatim),
+        "reject" => Ok(FreeText::Reject),
+        "ignore" => Ok(FreeText::Ignore),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("--free-text must be copy-verbatim, reject or ignore, not {value}"),
+        )),
+    }
+}
+
+fn parse_layout(value: &str) -> io::Result<Layout> {
+    match value {
+        "glyph-first" => Ok(Layout::GlyphFirst),
+        "name-first" => Ok(Layout::NameFirst),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("--layout must be glyph-first or name-first, not {value}"),
+        )),
+    }
+}
+
+fn parse_display_server(value: &str) -> io::Result<DisplayServer> {
+    match value {
+        "auto" => Ok(DisplayServer::Auto),
+        "wayland" => Ok(DisplayServer::Wayland),
+        "x11" => Ok(DisplayServer::X11),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("display_server must be auto, wayland or x11, not {value}"),
+        )),
+    }
+}
+
+fn parse_copy_mode(value: &str) -> io::Result<CopyMode> {
+    match value {
+        "path" => Ok(CopyMode::Path),
+        "image" => Ok(CopyMode::Image),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("--copy must be path or image, not {value}"),
+        )),
+    }
+}
+
+fn parse_selection_target(value: &str) -> io::Result<ClipboardSelection> {
+    match value {
+        "clipboard" => Ok(ClipboardSelection::Clipboard),
+        "primary" => Ok(ClipboardSelection::Primary),
+        "both" => Ok(ClipboardSelection::Both),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("--selection must be clipboard, primary or both, not {value}"),
+        )),
+    }
+}
+
+fn parse_repeat(value: &str) -> io::Result<usize> {
+    match parse_number(value)? {
+        0 => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--repeat must be at least 1",
+        )),
+        repeat => Ok(repeat),
+    }
+}
+
+fn parse_env(value: &str) -> io::Result<(String, String)> {
+    match value.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("--picker-env takes KEY=VALUE, not {value}"),
+        )),
+    }
+}
+
+fn parse_number(value: &str) -> io::Result<usize> {
+    value.parse().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid number: {value}"),
+        )
+    })
+}
+
+fn parse_sort_order(value: &str) -> io::Result<SortOrder> {
+    match value {
+        "name" => Ok(SortOrder::Name),
+        "codepoint" => Ok(SortOrder::Codepoint),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("--sort-all must be name or codepoint, not {value}"),
+        )),
+    }
+}
+
+fn parse_search_field(value: &str) -> io::Result<SearchField> {
+    match value {
+        "name" => Ok(SearchField::Name),
+        "description" => Ok(SearchField::Description),
+        "both" => Ok(SearchField::Both),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("--search-field must be name, description or both, not {value}"),
+        )),
+    }
+}
+
+fn parse_weight(value: &str) -> io::Result<f64> {
+    match value.parse::<f64>() {
+        Ok(weight) if weight >= 0.0 && weight.is_finite() => Ok(weight),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("a weight must be a non-negative number, not {value}"),
+        )),
+    }
+}
+
+// Parses durations like "30m", "12h" or "7d". A bare number is taken as days.
+fn parse_duration(value: &str) -> io::Result<Duration> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid duration: {value}"),
+        )
+    };
+
+    let unit_start = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(unit_start);
+    let number: u64 = number.parse().map_err(|_| invalid())?;
+    let seconds_per_unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "" | "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+
+    let seconds = number.checked_mul(seconds_per_unit).ok_or_else(invalid)?;
+    Ok(Duration::from_secs(seconds))
+}
+
+// Checks up front that the folder is a writable directory, so a misconfigured path fails here
+// with a clear message rather than at the history write after a pick.
+fn ensure_folder_exists(folder: &Path) -> Result<()> {
+    let folder_error =
+        |kind, problem: &str| io::Error::new(kind, format!("{} {problem}", folder.display()));
+
+    if folder.is_symlink() && !folder.exists() {
+        return Err(folder_error(
+            io::ErrorKind::NotFound,
+            "is a symlink to a missing folder",
+        ));
+    }
+    if folder.exists() && !folder.is_dir() {
+        return Err(folder_error(
+            io::ErrorKind::AlreadyExists,
+            "exists but is not a directory",
+        ));
+    }
+    if !folder.exists() {
+        fs::create_dir_all(folder)?;
+    }
+
+    let probe = folder.join(".rustoji-write-test");
+    match fs::File::create(&probe) {
+        Ok(_) => fs::remove_file(probe),
+        Err(err) => Err(folder_error(err.kind(), &format!("is not writable: {err}"))),
+    }
+}
+
+// emojis.json (or emojis_file_name), or its .gz when only the compressed one is there.
+fn emojis_file_path(data_folder: &Path, file_name: &str) -> PathBuf {
+    let unicode_emojis_file_path = data_folder.join(file_name);
+    let compressed_emojis_file_path = data_folder.join(format!("{file_name}.gz"));
+    if !unicode_emojis_file_path.exists() && compressed_emojis_file_path.exists() {
+        return compressed_emojis_file_path;
+    }
+    unicode_emojis_file_path
+}
+
+fn init_config(config_file_path: &Path, data_folder: &Path, force: bool) -> Result<()> {
+    if config_file_path.exists() && !force {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "{} already exists; use --force to overwrite it",
+                config_file_path.display()
+            ),
+        ));
+    }
+
+    if let Some(config_folder) = config_file_path.parent() {
+        ensure_folder_exists(config_folder)?;
+    }
+    ensure_folder_exists(data_folder)?;
+    fs::write(config_file_path, config::DEFAULT_CONFIG)?;
+    println!("Wrote {}", config_file_path.display());
+    Ok(())
+}
+
+// Moves the current config aside to config.toml.<unix time>.bak and writes the default one.
+fn reset_config(config_file_path: &Path, data_folder: &Path) -> Result<()> {
+    if config_file_path.exists() {
+        let now = history::now();
+        let mut backup_path = config_file_path.with_extension(format!("toml.{now}.bak"));
+        // never overwrite an earlier backup made within the same second
+        for attempt in 1.. {
+            if !backup_path.exists() {
+                break;
+            }
+            backup_path = config_file_path.with_extension(format!("toml.{now}-{attempt}.bak"));
+        }
+        fs::rename(config_file_path, &backup_path)?;
+        println!(
+            "Backed up {} to {}",
+            config_file_path.display(),
+            backup_path.display()
+        );
+    }
+    init_config(config_file_path, data_folder, true)
+}
+
+// One emoji name (or png file name) per line. Blank lines and lines starting with # are skipped.
+fn load_order(path: &Path) -> io::Result<Vec<String>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let mut seen = HashSet::new();
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter(|name| seen.insert(name.to_string()))
+        .map(str::to_string)
+        .collect())
+}
+
+fn load_json_or_default<T: serde::de::DeserializeOwned>(path: &Path) -> io::Result<T> {
+    // reading a directory fails with a bare "Is a directory"
+    if path.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("expected a file at {}, found a directory", path.display()),
+        ));
+    }
+    if path.exists() {
+        let file_content = read_maybe_gzipped(path)?;
+        let parsed_data: T = serde_json::from_str(&file_content)?;
+        Ok(parsed_data)
+    } else {
+        Ok(serde_json::from_str("{}").unwrap())
+    }
+}
+
+// Accepts either the plain {"name": "glyph"} map or an array of {"emoji", "name", "aliases"}
+// objects as used by many public emoji datasets. Aliases become extra entries for the same glyph.
+// The array form may also carry a "group" (or "category"), "keywords", a longer CLDR
+// "description" and an "ascii" fallback (or the first of its "emoticon"s), returned by name, and
+// its entries may instead be bare ["name", "glyph"] pairs.
+#[derive(Default)]
+struct EmojiDetails {
+    groups: HashMap<String, String>,
+    keywords: HashMap<String, String>, // space separated, only used for matching in the picker
+    descriptions: HashMap<String, String>, // matched instead of or as well as the name
+    fallbacks: HashMap<String, String>, // ascii text such as ":)", copied with --with-fallback
+    aliases: HashMap<String, String>,  // alias -> the emoji's own name, which history records
+}
+
+fn load_unicode_emojis(
+    path: &Path,
+    verbose: bool,
+) -> io::Result<(HashMap<String, String>, EmojiDetails)> {
+    let mut unicode_emojis = HashMap::new();
+    let mut details = EmojiDetails::default();
+    let mut normalized = 0;
+    let mut normalize = |name: &str| {
+        let clean = normalize_name(name);
+        if clean.as_deref() != Some(name) {
+            normalized += 1;
+        }
+        clean
+    };
+    let entries = match load_json_or_default(path)? {
+        EmojiFile::Map(pairs) => {
+            for (name, glyph) in pairs {
+                if let Some(name) = normalize(&name) {
+                    insert_unicode_emoji(&mut unicode_emojis, name, glyph);
+                }
+            }
+            report_normalized(path, normalized, verbose);
+            return Ok((unicode_emojis, details));
+        }
+        EmojiFile::List(entries) => entries,
+    };
+
+    let mut aliases = Vec::new();
+    for entry in &entries {
+        if let Some((name, glyph)) = emoji_pair(entry) {
+            if let Some(name) = normalize(name) {
+                insert_unicode_emoji(&mut unicode_emojis, name, glyph.to_string());
+            }
+            continue;
+        }
+        let (Some(glyph), Some(name)) = (entry["emoji"].as_str(), entry["name"].as_str()) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Every emoji entry needs an \"emoji\" and a \"name\", or has to be a [name, emoji] pair",
+            ));
+        };
+        let Some(name) = normalize(name) else {
+            continue;
+        };
+        let name = insert_unicode_emoji(&mut unicode_emojis, name, glyph.to_string());
+        let group = entry["group"].as_str().or(entry["category"].as_str());
+        if let Some(group) = group {
+            details.groups.insert(name.clone(), group.to_string());
+        }
+        let keywords: Vec<&str> = entry["keywords"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|keyword| keyword.as_str())
+            .collect();
+        let keywords = (!keywords.is_empty()).then(|| keywords.join(" "));
+        if let Some(keywords) = &keywords {
+            details.keywords.insert(name.clone(), keywords.clone());
+        }
+
+        if let Some(description) = entry["description"].as_str() {
+            details
+                .descriptions
+                .insert(name.clone(), description.to_string());
+        }
+        let fallback = entry["ascii"].as_str().or(match &entry["emoticon"] {
+            serde_json::Value::Array(emoticons) => emoticons.first().and_then(|e| e.as_str()),
+            emoticon => emoticon.as_str(),
+        });
+        if let Some(fallback) = fallback {
+            details.fallbacks.insert(name.clone(), fallback.to_string());
+        }
+
+        let entry_aliases = entry["aliases"].as_array().into_iter().flatten();
+        for alias in entry_aliases.filter_map(|alias| alias.as_str()) {
+            let Some(alias) = normalize(alias) else {
+                continue;
+            };
+            aliases.push((alias, name.clone(), glyph, group, keywords.clone()));
+        }
+    }
+
+    // an alias never shadows a real emoji name
+    for (alias, name, glyph, group, keywords) in aliases {
+        if unicode_emojis.contains_key(&alias) {
+            continue;
+        }
+        if let Some(group) = group {
+            details.groups.insert(alias.clone(), group.to_string());
+        }
+        if let Some(keywords) = keywords {
+            details.keywords.insert(alias.clone(), keywords);
+        }
+        for map in [&mut details.descriptions, &mut details.fallbacks] {
+            if let Some(value) = map.get(&name).cloned() {
+                map.insert(alias.clone(), value);
+            }
+        }
+        details.aliases.insert(alias.clone(), name);
+        unicode_emojis.insert(alias, glyph.to_string());
+    }
+
+    report_normalized(path, normalized, verbose);
+    Ok((unicode_emojis, details))
+}
+
+// A ["grinning face", "😀"] entry of the array form.
+fn emoji_pair(entry: &serde_json::Value) -> Option<(&str, &str)> {
+    match entry.as_array()?.as_slice() {
+        [name, glyph] => Some((name.as_str()?, glyph.as_str()?)),
+        _ => None,
+    }
+}
+
+// Trims a name and turns tabs and newlines inside it into spaces, so it can't break the picker's
+// one-entry-per-line, tab-separated format. `None` for names that are empty once trimmed.
+fn normalize_name(name: &str) -> Option<String> {
+    let name: String = name
+        .trim_matches(|c: char| c.is_whitespace() || c.is_control())
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect();
+    (!name.is_empty()).then_some(name)
+}
+
+fn report_normalized(path: &Path, normalized: usize, verbose: bool) {
+    if verbose && normalized > 0 {
+        eprintln!(
+            "{}: cleaned up or skipped {normalized} emoji names with stray whitespace",
+            path.display()
+        );
+    }
+}
+
+fn emoji_pack_paths(folder: &Path) -> io::Result<Vec<PathBuf>> {
+    if !folder.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(folder)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension() == Some(std::ffi::OsStr::new("json")) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+// Unlike --emojis files, packs don't override each other: an emoji whose name is taken is kept
+// under a disambiguated name, with its group, keywords, description and fallback.
+fn merge_emoji_pack(
+    unicode_emojis: &mut HashMap<String, String>,
+    details: &mut EmojiDetails,
+    pack: HashMap<String, String>,
+    mut pack_details: EmojiDetails,
+) {
+    let mut pack: Vec<(String, String)> = pack.into_iter().collect();
+    pack.sort();
+    for (name, glyph) in pack {
+        let merged_name = insert_unicode_emoji(unicode_emojis, name.clone(), glyph);
+        if let Some(group) = pack_details.groups.remove(&name) {
+            details.groups.insert(merged_name.clone(), group);
+        }
+        if let Some(keywords) = pack_details.keywords.remove(&name) {
+            details.keywords.insert(merged_name.clone(), keywords);
+        }
+        if let Some(description) = pack_details.descriptions.remove(&name) {
+            details
+                .descriptions
+                .insert(merged_name.clone(), description);
+        }
+        if let Some(fallback) = pack_details.fallbacks.remove(&name) {
+            details.fallbacks.insert(merged_name.clone(), fallback);
+        }
+        if let Some(canonical) = pack_details.aliases.remove(&name) {
+            details.aliases.insert(merged_name, canonical);
+        }
+    }
+}
+
+// A second emoji under a name already taken by a different glyph is kept under the name plus its
+// codepoints, e.g. "heart (U+2665 U+FE0F)", instead of replacing the first. Returns the name used.
+fn insert_unicode_emoji(
+    unicode_emojis: &mut HashMap<String, String>,
+    name: String,
+    glyph: String,
+) -> String {
+    let name = match unicode_emojis.get(&name) {
+        Some(existing) if *existing != glyph => {
+            let renamed = format!("{name} ({})", codepoints(&glyph));
+            log::note!("Duplicate emoji name {name:?}: listing {glyph} as {renamed:?}");
+            renamed
+        }
+        _ => name,
+    };
+    unicode_emojis.insert(name.clone(), glyph);
+    name
+}
+
+// `rustoji format-db <path>`: rewrites an emojis file pretty-printed and sorted by name, in
+// whichever of the two layouts it uses. Nothing is written unless the whole file is valid.
+fn format_db(path: &Path) -> io::Result<()> {
+    let bytes = fs::read(path)?;
+    if bytes.starts_with(&GZIP_MAGIC) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is gzipped, decompress it first", path.display()),
+        ));
+    }
+    let invalid = |msg: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {msg}", path.display()),
+        )
+    };
+
+    let (formatted, count) = match serde_json::from_slice(&bytes)
+        .map_err(|err| invalid(err.to_string()))?
+    {
+        EmojiFile::Map(pairs) => {
+            // serde_json's map keeps its keys sorted
+            let mut map = serde_json::Map::new();
+            for (name, glyph) in pairs {
+                if map.insert(name.clone(), glyph.into()).is_some() {
+                    return Err(invalid(format!("duplicate name {name:?}")));
+                }
+            }
+            let count = map.len();
+            (serde_json::Value::Object(map), count)
+        }
+        EmojiFile::List(mut entries) => {
+            for (number, entry) in entries.iter().enumerate() {
+                let object = entry["emoji"].is_string() && entry["name"].is_string();
+                if !object && emoji_pair(entry).is_none() {
+                    return Err(invalid(format!(
+                            "entry {} needs an \"emoji\" and a \"name\", or has to be a [name, emoji] pair",
+                            number + 1
+                        )));
+                }
+            }
+            let name = |entry: &serde_json::Value| match emoji_pair(entry) {
+                Some((name, _)) => name.to_string(),
+                None => entry["name"].as_str().unwrap_or_default().to_string(),
+            };
+            entries.sort_by_key(name);
+            let count = entries.len();
+            (serde_json::Value::Array(entries), count)
+        }
+    };
+
+    let mut text = serde_json::to_string_pretty(&formatted)?;
+    text.push('\n');
+    let temp_path = path.with_extension("json.tmp");
+    fs::write(&temp_path, text)?;
+    fs::rename(temp_path, path)?;
+    println!("Formatted {count} emojis in {}", path.display());
+    Ok(())
+}
+
+// The two emojis file layouts. The map form is read pair by pair rather than into a map, so
+// duplicate names reach insert_unicode_emoji instead of silently keeping the last one.
+enum EmojiFile {
+    Map(Vec<(String, String)>),
+    List(Vec<serde_json::Value>),
+}
+
+impl<'de> serde::Deserialize<'de> for EmojiFile {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        deserializer.deserialize_any(EmojiFileVisitor)
+    }
+}
+
+struct EmojiFileVisitor;
+
+impl<'de> serde::de::Visitor<'de> for EmojiFileVisitor {
+    type Value = EmojiFile;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a {\"name\": \"emoji\"} map or an array of emoji objects")
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(
+        self,
+        mut map: A,
+    ) -> std::result::Result<EmojiFile, A::Error> {
+        let mut pairs = Vec::new();
+        while let Some(pair) = map.next_entry()? {
+            pairs.push(pair);
+        }
+        Ok(EmojiFile::Map(pairs))
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(
+        self,
+        mut seq: A,
+    ) -> std::result::Result<EmojiFile, A::Error> {
+        let mut entries = Vec::new();
+        while let Some(entry) = seq.next_element()? {
+            entries.push(entry);
+        }
+        Ok(EmojiFile::List(entries))
+    }
+}
+
+// Kaomoji and snippets share the name -> text format and the text copy path. A name an emoji
+// already uses gets a suffix instead of shadowing it.
+fn merge_text_entries(
+    unicode_emojis: &mut HashMap<String, String>,
+    path: &Path,
+    kind: &str,
+) -> io::Result<()> {
+    let entries: HashMap<String, String> = load_json_or_default(path)?;
+    for (name, text) in entries {
+        // names are cleaned up like those in emojis.json, the picker reads one entry per line
+        let Some(name) = normalize_name(&name) else {
+            continue;
+        };
+        let name = if unicode_emojis.contains_key(&name) {
+            format!("{name} ({kind})")
+        } else {
+            name
+        };
+        unicode_emojis.insert(name, text);
+    }
+    Ok(())
+}
+
+// Gzip-compressed files are recognized by their magic bytes and decompressed with gzip.
+fn read_maybe_gzipped(path: &Path) -> io::Result<String> {
+    let mut bytes = fs::read(path)?;
+    if bytes.starts_with(&GZIP_MAGIC) {
+        let output = Command::new("gzip").arg("-dc").arg(path).output()?;
+        if !output.status.success() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Failed to decompress {}", path.display()),
+            ));
+        }
+        bytes = output.stdout;
+    }
+    String::from_utf8(bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn fetch_unicode_emojis_file(path: &Path, timeout: Option<Duration>) -> io::Result<()> {
+    download_unicode_emojis_file("wget", path, timeout)
+}
+
+// `wget` is the program the download runs, swapped for a stub in tests.
+fn download_unicode_emojis_file(
+    wget: &str,
+    path: &Path,
+    timeout: Option<Duration>,
+) -> io::Result<()> {
+    if UNICODE_EMOJIS_FILE_URL.is_empty() {
+        log::note!("No URL provided for fetching the emojis file.");
+        return Ok(());
+    }
+    log::debug!(
+        "downloading {UNICODE_EMOJIS_FILE_URL} to {}",
+        path.display()
+    );
+    // wget -O doesn't create missing folders
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut wget = Command::new(wget)
+        .args([UNICODE_EMOJIS_FILE_URL, "-O", path.to_str().unwrap()])
+        .spawn()?;
+    let status = wait_timeout(&mut wget, timeout)?;
+    log::debug!("wget exited with {status:?}");
+
+    if !status.is_some_and(|status| status.success()) {
+        match status {
+            Some(_) => log::note!("Failed to download the emojis file."),
+            None => log::note!("Downloading the emojis file timed out."),
+        }
+        // wget leaves an empty or partial file behind, which would stop the next run from
+        // downloading it again
+        let _ = fs::remove_file(path);
+        return Ok(());
+    }
+
+    // where and when the file came from, for --db-info
+    let meta = serde_json::json!({ "source": UNICODE_EMOJIS_FILE_URL, "fetched": history::now() });
+    fs::write(db_meta_path(path), serde_json::to_string_pretty(&meta)?)
+}
+
+// emojis.meta.json next to emojis.json, named after it when emojis_file_name is set.
+fn db_meta_path(emojis_file_path: &Path) -> PathBuf {
+    let file_name = emojis_file_path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let stem = file_name.strip_suffix(".gz").unwrap_or(&file_name);
+    let stem = stem.strip_suffix(".json").unwrap_or(stem);
+    emojis_file_path.with_file_name(format!("{stem}.meta.json"))
+}
+
+fn print_db_info(
+    emojis_file_path: &Path,
+    unicode_emojis: &HashMap<String, String>,
+) -> io::Result<()> {
+    println!("file:     {}", emojis_file_path.display());
+    println!("emojis:   {}", unicode_emojis.len());
+    let modified = fs::metadata(emojis_file_path)?
+        .modified()?
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |modified| modified.as_secs());
+    println!(
+        "modified: {} ({})",
+        history::format_relative(modified),
+        history::format_local_time(modified)
+    );
+
+    let meta: serde_json::Value = load_json_or_default(&db_meta_path(emojis_file_path))?;
+    match (meta["source"].as_str(), meta["fetched"].as_u64()) {
+        (Some(source), Some(fetched)) => {
+            println!("source:   {source}");
+            println!(
+                "fetched:  {} ({})",
+                history::format_relative(fetched),
+                history::format_local_time(fetched)
+            );
+        }
+        _ => println!("source:   unknown, not downloaded by rustoji"),
+    }
+    Ok(())
+}
+
+// Waits for the child to exit, or kills it once `timeout` has passed and returns `None`.
+fn wait_timeout(child: &mut Child, timeout: Option<Duration>) -> io::Result<Option<ExitStatus>> {
+    let Some(timeout) = timeout else {
+        return child.wait().map(Some);
+    };
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            child.kill()?;
+            child.wait()?;
+            return Ok(None);
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+// Png emojis are collected from subfolders too. Symlinked files and folders are followed unless
+// --no-follow-symlinks is given; each folder is visited once, so symlink loops end there.
+fn collect_png_emojis_and_filter(
+    path: &Path,
+    emojis_to_filter_out: &Vec<&String>,
+    new_window: Option<Duration>,
+    args: &Args,
+) -> io::Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    if path.exists() {
+        collect_png_files(path, args.follow_symlinks, &mut HashSet::new(), &mut found)?;
+    }
+    if args.validate_images {
+        found.retain(|emoji_path| {
+            let valid = is_png(emoji_path);
+            if !valid && args.verbose {
+                eprintln!("Skipping {}: not a valid png file", emoji_path.display());
+            }
+            valid
+        });
+    }
+
+    let mut all_png_emojis = Vec::new();
+    let mut new_png_emojis = Vec::new();
+    let now = SystemTime::now();
+    for emoji_path in found {
+        let name = png_emoji_name(path, &emoji_path);
+        if emojis_to_filter_out.contains(&&name) {
+            continue;
+        }
+        // a newline would split the entry and \0 or \x1f would break the icon protocol, and the
+        // label has to stay the file name for the pick to find the file again
+        if name.contains(char::is_control) {
+            log::note!("Skipping {name:?}: control characters in the file name");
+            continue;
+        }
+        if let Some(window) = new_window {
+            let modified = emoji_path.metadata()?.modified()?;
+            if now.duration_since(modified).unwrap_or_default() <= window {
+                new_png_emojis.push((emoji_path, modified));
+                continue;
+            }
+        }
+        all_png_emojis.push(emoji_path);
+    }
+
+    // newest first, ahead of everything else
+    new_png_emojis.sort_by_key(|&(_, modified)| std::cmp::Reverse(modified));
+    let mut png_emojis: Vec<PathBuf> = new_png_emojis.into_iter().map(|(path, _)| path).collect();
+    png_emojis.append(&mut all_png_emojis);
+    Ok(png_emojis)
+}
+
+// `rustoji import-pack <folder>`: copies the images of an exported Discord or Slack emoji pack
+// into the png emojis folder, keeping its subfolders, under the names they're picked by: the
+// pack's manifest's, if it has one, or else the file's. Webp and gif files, which Discord exports
+// too, are converted to png with ImageMagick, taking the first frame of animated ones. Files that
+// would replace an existing png emoji are left out.
+fn import_pack(source: &Path, png_folder: &Path) -> io::Result<()> {
+    if !source.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} is not a folder", source.display()),
+        ));
+    }
+    let pack_names = load_pack_names(source)?;
+    let mut found = Vec::new();
+    collect_files(source, true, &mut HashSet::new(), &mut found, is_pack_image)?;
+    found.sort();
+
+    let (mut imported, mut existing) = (0, 0);
+    for path in found {
+        let relative = path.strip_prefix(source).unwrap_or(&path);
+        let stem = relative.file_stem().unwrap_or_default().to_string_lossy();
+        let name = pack_names
+            .get(relative.to_string_lossy().as_ref())
+            .or_else(|| pack_names.get(stem.as_ref()))
+            .map_or_else(|| pack_emoji_name(&stem), String::as_str);
+        let target = png_folder
+            .join(relative.parent().unwrap_or(Path::new("")))
+            .join(format!("{name}.png"));
+        if target.exists() {
+            existing += 1;
+            continue;
+        }
+        log::debug!("importing {} as {}", path.display(), target.display());
+        if let Some(folder) = target.parent() {
+            fs::create_dir_all(folder)?;
+        }
+        if has_png_extension(&path) {
+            fs::copy(&path, &target)?;
+        } else {
+            convert_to_png(&path, &target)?;
+        }
+        imported += 1;
+    }
+
+    println!("Imported {imported} emojis into {}", png_folder.display());
+    if existing > 0 {
+        println!("Skipped {existing} whose name a png emoji already has");
+    }
+    Ok(())
+}
+
+// The images import-pack takes, png or convertible to it.
+fn is_pack_image(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| {
+        ["png", "webp", "gif"]
+            .iter()
+            .any(|known| extension.eq_ignore_ascii_case(known))
+    })
+}
+
+// Written aside first, like a download, so a failed conversion never shows up as an emoji.
+fn convert_to_png(path: &Path, target: &Path) -> io::Result<()> {
+    let partial_path = target.with_extension("png.partial");
+    let mut first_frame = path.as_os_str().to_owned();
+    first_frame.push("[0]");
+    let converted = imagemagick(&[
+        &first_frame,
+        format!("png:{}", partial_path.display()).as_ref(),
+    ])
+    .and_then(|status| {
+        if status.success() {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!("ImageMagick failed ({status})")))
+        }
+    });
+    if let Err(err) = converted {
+        let _ = fs::remove_file(&partial_path);
+        return Err(io::Error::new(
+            err.kind(),
+            format!(
+                "Failed to convert {} to png: {err}; install ImageMagick or leave it out of the pack",
+                path.display()
+            ),
+        ));
+    }
+    fs::rename(&partial_path, target)
+}
+
+// The names a pack's names.json or manifest.json gives its files, by file name relative to the
+// pack (with or without the extension) or by Discord emoji id, which is what Discord exports
+// name the files by. Either {"pp_01.png": "party_parrot"} or [{"id": "3965...", "name":
+// "blobcat"}], the latter also with "file" in place of "id". Names that can't name a file are
+// ignored.
+fn load_pack_names(source: &Path) -> io::Result<HashMap<String, String>> {
+    let Some(path) = ["names.json", "manifest.json"]
+        .iter()
+        .map(|file_name| source.join(file_name))
+        .find(|path| path.is_file())
+    else {
+        return Ok(HashMap::new());
+    };
+    let invalid = |msg: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {msg}", path.display()),
+        )
+    };
+    let manifest: serde_json::Value =
+        serde_json::from_slice(&fs::read(&path)?).map_err(|err| invalid(&err.to_string()))?;
+
+    let pairs: Vec<(String, String)> = match manifest {
+        serde_json::Value::Object(names) => names
+            .into_iter()
+            .map(|(file, name)| match name {
+                serde_json::Value::String(name) => Ok((file, name)),
+                _ => Err(invalid(&format!("the name of {file:?} isn't a string"))),
+            })
+            .collect::<io::Result<_>>()?,
+        serde_json::Value::Array(entries) => entries
+            .iter()
+            .enumerate()
+            .map(|(number, entry)| {
+                let file = entry["id"].as_str().or(entry["file"].as_str());
+                match (file, entry["name"].as_str()) {
+                    (Some(file), Some(name)) => Ok((file.to_string(), name.to_string())),
+                    _ => Err(invalid(&format!(
+                        "entry {} needs a \"name\" and an \"id\" or \"file\"",
+                        number + 1
+                    ))),
+                }
+            })
+            .collect::<io::Result<_>>()?,
+        _ => {
+            return Err(invalid(
+                "expected an object of file names to names, or a list of emojis",
+            ))
+        }
+    };
+
+    let mut names = HashMap::new();
+    for (file, name) in pairs {
+        let plain = normalize_name(name.trim_matches(':'))
+            .filter(|name| !name.contains('/') && !name.starts_with('.'));
+        match plain {
+            Some(name) => {
+                names.insert(file, name);
+            }
+            None => log::note!(
+                "Ignoring the name {name:?} for {file} in {}",
+                path.display()
+            ),
+        }
+    }
+    Ok(names)
+}
+
+// Downloads a png into the png emojis folder with wget, like emojis.json, as <name>.png or under
+// the url's file name. The name may put it in a subfolder, "cats/blob". Anything but a png is
+// thrown away, and an existing emoji is only replaced with --force.
+fn add_emoji(url: &str, name: Option<&str>, png_folder: &Path, args: &Args) -> io::Result<()> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+    let name = match name {
+        Some(name) => name.strip_suffix(".png").unwrap_or(name).to_string(),
+        None => {
+            let path = url.split(['?', '#']).next().unwrap_or(url);
+            let file_name = path.trim_end_matches('/').rsplit('/').next().unwrap_or("");
+            let stem = Path::new(file_name).file_stem().unwrap_or_default();
+            pack_emoji_name(&stem.to_string_lossy()).to_string()
+        }
+    };
+    let relative = Path::new(&name);
+    let plain = relative
+        .components()
+        .all(|component| matches!(component, std::path::Component::Normal(_)));
+    if name.is_empty() || !plain || name.contains(char::is_control) {
+        return Err(invalid(format!(
+            "{name:?} can't name a png emoji, give one after the url"
+        )));
+    }
+    let target = png_folder.join(format!("{name}.png"));
+    if target.exists() && !args.force {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "{} already exists, use --force to replace it",
+                target.display()
+            ),
+        ));
+    }
+    if let Some(folder) = target.parent() {
+        fs::create_dir_all(folder)?;
+    }
+
+    // downloaded aside first, so a failed download never replaces an emoji or shows up as one
+    let partial_path = target.with_extension("png.partial");
+    log::debug!("downloading {url} to {}", partial_path.display());
+    let mut wget = Command::new("wget")
+        .arg(url)
+        .arg("-O")
+        .arg(&partial_path)
+        .spawn()?;
+    let status = wait_timeout(&mut wget, args.fetch_timeout)?;
+    log::debug!("wget exited with {status:?}");
+    if !status.is_some_and(|status| status.success()) || !is_png(&partial_path) {
+        let _ = fs::remove_file(&partial_path);
+        return Err(io::Error::other(match status {
+            Some(status) if status.success() => format!("{url} isn't a png image"),
+            Some(status) => format!("Failed to download {url} ({status})"),
+            None => format!("Downloading {url} timed out"),
+        }));
+    }
+    fs::rename(&partial_path, &target)?;
+    println!("Added {name}.png to {}", png_folder.display());
+    Ok(())
+}
+
+// Slack names are written like :party_parrot:, and Discord exports often end the name with the
+// emoji's numeric id, as in blobcat-396521773144866826. Neither is part of the name.
+fn pack_emoji_name(stem: &str) -> &str {
+    let name = stem.trim_matches(':');
+    match name.rsplit_once(['-', '_']) {
+        Some((base, id))
+            if !base.is_empty() && id.len() >= 17 && id.bytes().all(|b| b.is_ascii_digit()) =>
+        {
+            base
+        }
+        _ => name,
+    }
+}
+
+fn collect_png_files(
+    folder: &Path,
+    follow_symlinks: bool,
+    visited: &mut HashSet<PathBuf>,
+    found: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    collect_files(folder, follow_symlinks, visited, found, has_png_extension)
+}
+
+// Only `folder` itself has to be readable; a subfolder that can't be read is noted and skipped so
+// the rest of the emojis still show up.
+fn collect_files(
+    folder: &Path,
+    follow_symlinks: bool,
+    visited: &mut HashSet<PathBuf>,
+    found: &mut Vec<PathBuf>,
+    wanted: fn(&Path) -> bool,
+) -> io::Result<()> {
+    if !visited.insert(fs::canonicalize(folder)?) {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(folder)? {
+        let entry = entry?;
+        if !follow_symlinks && entry.file_type()?.is_symlink() {
+            continue;
+        }
+        let path = entry.path();
+        if path.is_dir() {
+            if let Err(err) = collect_files(&path, follow_symlinks, visited, found, wanted) {
+                log::note!("Skipping {}: {err}", path.display());
+            }
+        } else if path.is_file() && wanted(&path) {
+            found.push(path);
+        }
+    }
+    Ok(())
+}
+
+// "blob.png" and "blob.PNG" alike.
+fn has_png_extension(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("png"))
+}
+
+// Checks the png signature, which also rules out empty and unreadable files.
+fn is_png(path: &Path) -> bool {
+    const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+    let mut signature = [0; 8];
+    fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut signature))
+        .is_ok_and(|()| signature == PNG_SIGNATURE)
+}
+
+// The path relative to the png emojis folder, e.g. "cats/blob.png". It names the emoji in the
+// picker and in history.
+fn png_emoji_name(expanded_png_emojis_path: &Path, emoji_path: &Path) -> String {
+    emoji_path
+        .strip_prefix(expanded_png_emojis_path)
+        .unwrap_or(emoji_path)
+        .to_string_lossy()
+        .into_owned()
+}
+
+// names.json in the png emojis folder maps file names, relative to the folder like history keys,
+// to the names shown instead: {"pack/emoji_001.png": "party parrot"}. A name has to lead back to
+// a single file, so one that's already taken (or has a separator in it) is ignored.
+fn load_png_names(expanded_png_emojis_path: &Path) -> io::Result<HashMap<String, String>> {
+    let path = expanded_png_emojis_path.join("names.json");
+    let names: BTreeMap<String, String> = load_json_or_default(&path)?;
+    let mut png_names = HashMap::new();
+    let mut taken = HashSet::new();
+    for (file_name, name) in names {
+        if name.contains(FIELD_SEPARATOR) || !taken.insert(name.clone()) {
+            log::note!(
+                "Ignoring the name {name:?} for {file_name} in {}",
+                path.display()
+            );
+            continue;
+        }
+        png_names.insert(file_name, name);
+    }
+    Ok(png_names)
+}
+
+// A png emoji's group is the subfolder it's in, if any.
+fn png_emoji_group(name: &str) -> Option<String> {
+    Path::new(name)
+        .parent()
+        .filter(|folder| !folder.as_os_str().is_empty())
+        .map(|folder| folder.to_string_lossy().into_owned())
+}
+
+// The entries are only built once the picker is running.
+fn run_picker(args: &Args, entries: impl FnOnce() -> Vec<String>) -> io::Result<String> {
+    let picker = args.picker;
+    let mut command = Command::new(picker.name);
+
+    command.args(picker.base_args);
+    command.args(picker.counter_flag);
+    // options a picker has no flag for are dropped
+    let options = [
+        (picker.prompt_flag, &args.prompt),
+        (picker.query_flag, &args.query),
+        (picker.dpi_aware_flag, &args.dpi_aware),
+        (picker.width_flag, &args.width),
+        (picker.lines_flag, &args.lines),
+        (picker.anchor_flag, &args.anchor),
+    ];
+    for (flag, value) in options {
+        if let (Some(flag), Some(value)) = (flag, value) {
+            command.arg(flag).arg(value);
+        }
+    }
+    command.args(&args.picker_args);
+    command.envs(args.picker_env.iter().map(|(key, value)| (key, value)));
+
+    log::debug!("spawning the picker: {command:?}");
+    // the pickers can't open a window without a Wayland or X11 session, so ask on the terminal
+    // instead; a --copy-cmd or --wayland doesn't give them one
+    let on_tty = !graphical_session() && io::stdin().is_terminal();
+    let spawned = if on_tty {
+        Err(io::Error::from(io::ErrorKind::NotFound))
+    } else {
+        command.stdin(Stdio::piped()).stdout(Stdio::piped()).spawn()
+    };
+    let mut child = match spawned {
+        Err(err)
+            if err.kind() == io::ErrorKind::NotFound
+                && (on_tty || args.builtin_picker && io::stdin().is_terminal()) =>
+        {
+            if !on_tty {
+                log::note!("{} not found, using the built-in picker", picker.name);
+            }
+            return builtin_picker(&entries());
+        }
+        spawned => spawned?,
+    };
+
+    // shortened label -> the full entry it stands for
+    let mut shortened = HashMap::new();
+    if let Some(stdin) = child.stdin.as_mut() {
+        for entry in entries() {
+            let entry = match args.max_name_len {
+                Some(max_name_len) => {
+                    shorten_entry(entry, args.layout, max_name_len, &mut shortened)
+                }
+                None => entry,
+            };
+            writeln!(stdin, "{entry}")?;
+        }
+    }
+
+    // the picker only sees the end of its input once stdin is closed
+    drop(child.stdin.take());
+    if wait_timeout(&mut child, args.picker_timeout)?.is_none() {
+        log::note!(
+            "{} closed after {:?} without a pick",
+            picker.name,
+            args.picker_timeout.unwrap()
+        );
+        return Ok(String::new());
+    }
+    let output = child.wait_with_output()?;
+    log::debug!("picker exited with {}", output.status);
+    // a cancelled picker exits with a status, one killed by the compositor (or anything else but
+    // our own Ctrl-C) is an error rather than an empty selection
+    if let Some(signal) = output.status.signal() {
+        if !INTERRUPTED.load(Ordering::SeqCst) {
+            return Err(io::Error::other(format!(
+                "{} was killed by signal {signal}",
+                picker.name
+            )));
+        }
+    }
+    // cancelling is an empty pick, but a picker that fails some other way without printing
+    // anything has most likely crashed or couldn't open its window
+    if output.stdout.is_empty() && !INTERRUPTED.load(Ordering::SeqCst) {
+        if let Some(code) = output.status.code() {
+            if code != 0 && !picker.cancel_codes.contains(&code) {
+                return Err(io::Error::other(format!(
+                    "{} failed with exit code {code}",
+                    picker.name
+                )));
+            }
+        }
+    }
+    let output_str = String::from_utf8_lossy(&output.stdout);
+    let output_str = strip_line_ending(&output_str);
+    match shortened.remove(output_str) {
+        Some(full_entry) => Ok(full_entry),
+        None => Ok(output_str.to_string()),
+    }
+}
+
+// Only drops the newline a picker terminates the selection with (\r\n from some); whitespace may
+// be part of the emoji.
+fn strip_line_ending(selection: &str) -> &str {
+    selection
+        .strip_suffix("\r\n")
+        .or_else(|| selection.strip_suffix('\n'))
+        .unwrap_or(selection)
+}
+
+// --select: picks the entry the query matches best, in the same form a picker would print it. An
+// exact name wins outright; otherwise a name starting with the query beats one containing it,
+// which beats a keyword match, which beats the query's letters appearing in order in the name.
+// Within a kind of match, emojis in history get a boost of up to --select-history-boost, scaled
+// by how often they were used, so frequent picks win ties. Below 1 it never lifts an emoji past
+// a better kind of match. Remaining ties are an error listing them, or go to the first listed
+// (the most used) with --first.
+fn select_entry(
+    args: &Args,
+    entries: &[String],
+    query: &str,
+    history: &history::History,
+    png_names: &HashMap<String, String>,
+) -> io::Result<String> {
+    // a whole line printed by --no-picker selects that entry
+    let selectable = |entry: &String| entry.split('\0').next().unwrap_or(entry).to_string();
+    if let Some(entry) = entries.iter().map(selectable).find(|entry| entry == query) {
+        return Ok(entry);
+    }
+    let query = query.to_lowercase();
+    let most_used = history.values().map(|entry| entry.count).max().unwrap_or(0);
+    let boost = |key: &str| match history.get(key) {
+        Some(entry) if most_used > 0 => {
+            args.select_history_boost * entry.count as f64 / most_used as f64
+        }
+        _ => 0.0,
+    };
+    let mut best = Vec::new();
+    let mut best_score = 0.0;
+    let mut best_kind = 0;
+    for entry in entries {
+        let selectable = entry.split('\0').next().unwrap_or(entry);
+        let mut fields = selectable.split(FIELD_SEPARATOR);
+        // history knows unicode emojis by name and png emojis by file name
+        let (name, keywords, history_key) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(first), Some(second), keywords) => {
+                let name = match args.layout {
+                    Layout::GlyphFirst => second,
+                    Layout::NameFirst => first,
+                };
+                (name, keywords.unwrap_or(""), name)
+            }
+            // a png emoji, matched by its file name without the extension; one labelled by
+            // names.json is matched by that name as it is, and still known to history by its file
+            _ => (
+                selectable
+                    .rsplit_once('.')
+                    .filter(|_| has_png_extension(Path::new(selectable)))
+                    .map_or(selectable, |(stem, _)| stem),
+                "",
+                png_names
+                    .iter()
+                    .find(|&(_, name)| name == selectable)
+                    .map_or(selectable, |(file_name, _)| file_name.as_str()),
+            ),
+        };
+        // entries with nothing else to match, like png emojis, are still matched by name
+        let matched_name = match args.search_field {
+            SearchField::Description if !keywords.is_empty() => "",
+            _ => name,
+        };
+        let Some(kind) = match_score(
+            &query,
+            &matched_name.to_lowercase(),
+            &keywords.to_lowercase(),
+        ) else {
+            continue;
+        };
+        let score = kind as f64 + boost(history_key);
+        if score > best_score {
+            best_score = score;
+            best_kind = kind;
+            best.clear();
+        }
+        if score == best_score {
+            best.push((name, selectable));
+        }
+    }
+
+    match best.as_slice() {
+        [] => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No emoji matches {query:?}"),
+        )),
+        [(_, selectable), ..] if best.len() == 1 || best_kind == EXACT_MATCH || args.first => {
+            Ok(selectable.to_string())
+        }
+        _ => {
+            let names: Vec<&str> = best.iter().take(10).map(|&(name, _)| name).collect();
+            let more = if best.len() > names.len() {
+                ", ..."
+            } else {
+                ""
+            };
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{query:?} matches {} emojis equally well: {}{more}; add --first to take the first",
+                    best.len(),
+                    names.join(", ")
+                ),
+            ))
+        }
+    }
+}
+
+const EXACT_MATCH: u8 = 5;
+
+fn match_score(query: &str, name: &str, keywords: &str) -> Option<u8> {
+    if name == query {
+        Some(EXACT_MATCH)
+    } else if name.starts_with(query) {
+        Some(4)
+    } else if name.contains(query) {
+        Some(3)
+    } else if keywords
+        .split(' ')
+        .any(|keyword| keyword.starts_with(query))
+        // a phrase from a description
+        || query.contains(' ') && keywords.contains(query)
+    {
+        Some(2)
+    } else {
+        let mut letters = name.chars();
+        query
+            .chars()
+            .all(|c| letters.any(|letter| letter == c))
+            .then_some(1)
+    }
+}
+
+// The entries as a picker would get them, without the picker-specific icon metadata.
+fn print_entries(entries: &[String]) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    for entry in entries {
+        writeln!(stdout, "{}", entry.split('\0').next().unwrap_or(entry))?;
+    }
+    stdout.flush()
+}
+
+// --dmenu-passthrough puts rustoji in front of a selector of the user's own. The entries go to
+// stdout one per line, as --no-picker prints them, and then stdout is closed so the selector sees
+// the end of its input. The selection comes back as one line on stdin, or on the file descriptor
+// given with --selection-fd, and is copied and recorded like a picker's; an empty line or none at
+// all cancels. Anything rustoji has to say after the entries goes to stderr. With a fifo:
+//
+//   mkfifo /tmp/pick && rustoji --dmenu-passthrough < /tmp/pick | dmenu > /tmp/pick
+//
+// or from a script that keeps its own descriptor for the answer:
+//
+//   rustoji none --selection-fd 3 3< answer
+fn dmenu_passthrough(entries: &[String], selection_fd: Option<i32>) -> io::Result<String> {
+    use std::os::fd::{AsRawFd, FromRawFd};
+
+    if let Some(fd) = selection_fd {
+        // SAFETY: fcntl only asks whether the descriptor is open
+        if unsafe { libc::fcntl(fd, libc::F_GETFD) } < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("--selection-fd {fd} isn't an open file descriptor"),
+            ));
+        }
+    }
+    match print_entries(entries) {
+        // a selector that exits before reading everything can still have picked
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => {}
+        result => result?,
+    }
+    let dev_null = fs::OpenOptions::new().write(true).open("/dev/null")?;
+    // SAFETY: both descriptors are open; stdout is only replaced, never left dangling
+    if unsafe { libc::dup2(dev_null.as_raw_fd(), libc::STDOUT_FILENO) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut selection = String::new();
+    match selection_fd {
+        Some(fd) => {
+            // SAFETY: the descriptor is open and nothing else in rustoji uses it
+            let file = unsafe { fs::File::from_raw_fd(fd) };
+            io::BufReader::new(file).read_line(&mut selection)?;
+        }
+        None => {
+            io::stdin().lock().read_line(&mut selection)?;
+        }
+    }
+    Ok(strip_line_ending(&selection).to_string())
+}
+
+// The last resort with --builtin-picker when the picker isn't installed: filter the entries by a
+// search term on the terminal, then choose one by number. Returns what a picker would have
+// printed: the chosen entry, the search term when nothing matched, or nothing.
+fn builtin_picker(entries: &[String]) -> io::Result<String> {
+    let mut stdin = io::stdin().lock();
+    let mut read_answer = |question: &str| -> io::Result<String> {
+        eprint!("{question}");
+        let mut answer = String::new();
+        stdin.read_line(&mut answer)?;
+        Ok(answer.trim_end_matches('\n').to_string())
+    };
+
+    let query = read_answer("search: ")?;
+    let matches: Vec<&str> = entries
+        .iter()
+        .map(|entry| entry.split('\0').next().unwrap_or(entry))
+        .filter(|entry| entry.to_lowercase().contains(&query.to_lowercase()))
+        .collect();
+    if matches.is_empty() {
+        return Ok(query);
+    }
+
+    for (number, entry) in matches.iter().enumerate() {
+        eprintln!(
+            "{:>5}  {}",
+            number + 1,
+            entry.replace(FIELD_SEPARATOR, "  ")
+        );
+    }
+    let choice = read_answer("number (empty to cancel): ")?;
+    if choice.is_empty() {
+        return Ok(String::new());
+    }
+    match parse_number(&choice)? {
+        number @ 1.. if number <= matches.len() => Ok(matches[number - 1].to_string()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("No entry number {choice}"),
+        )),
+    }
+}
+
+// Cuts the name shown for an entry to `max_name_len` characters, ending it with an ellipsis. The
+// picker returns only the part before any icon suffix, so that part is what `shortened` maps back
+// to the full entry. A label that would be ambiguous is left whole.
+fn shorten_entry(
+    entry: String,
+    layout: Layout,
+    max_name_len: usize,
+    shortened: &mut HashMap<String, String>,
+) -> String {
+    let (selectable, icon) = entry.split_at(entry.find('\0').unwrap_or(entry.len()));
+    let (glyph, name) = match selectable.split_once(FIELD_SEPARATOR) {
+        Some((glyph, name)) if layout == Layout::GlyphFirst => {
+            (format!("{glyph}{FIELD_SEPARATOR}"), name)
+        }
+        _ => (String::new(), selectable),
+    };
+    // keywords (and a trailing glyph) stay whole, they're only there to be matched
+    let (name, keywords) = name.split_at(name.find(FIELD_SEPARATOR).unwrap_or(name.len()));
+    if name.chars().count() <= max_name_len {
+        return entry;
+    }
+
+    let short_name: String = name.chars().take(max_name_len.saturating_sub(1)).collect();
+    let label = format!("{glyph}{short_name}…{keywords}");
+    if shortened.contains_key(&label) {
+        return entry;
+    }
+    let shown = format!("{label}{icon}");
+    shortened.insert(label, selectable.to_string());
+    shown
+}
+
+// History first, then png emojis, then the remaining unicode emojis, capped at --limit.
+fn picker_entries(
+    args: &Args,
+    unicode_emojis: &HashMap<String, String>,
+    details: &EmojiDetails,
+    png_emojis: &Vec<PathBuf>,
+    png_names: &HashMap<String, String>,
+    sorted_history: &Vec<&String>,
+    expanded_png_emojis_path: &Path,
+) -> Vec<String> {
+    let mut entries = Vec::new();
+    // png entries listed so far, for --max-icons; recents come first, so they keep their icons
+    let mut png_count = 0;
+    let mut png_icon = || {
+        png_count += 1;
+        args.max_icons
+            .is_none_or(|max_icons| png_count <= max_icons)
+    };
+    // details are looked up by the untoned name
+    let entry = |glyph: &str, name: &str, key: &str| {
+        let group = details.groups.get(key);
+        let icon = group.and_then(|group| args.category_icons.get(group));
+        let description = match args.search_field {
+            SearchField::Name => None,
+            _ => details.descriptions.get(key),
+        };
+        with_icon(
+            args,
+            unicode_entry(
+                args,
+                glyph,
+                name,
+                details.keywords.get(key),
+                description,
+                group.filter(|_| args.search_category),
+            ),
+            icon.map(String::as_str),
+        )
+    };
+
+    for emoji in sorted_history {
+        let emoji_path = expanded_png_emojis_path.join(emoji);
+        // collect_png_emojis_and_filter left history's png emojis out so they're listed once,
+        // here. A png history key wins only while its file exists (and passes --validate-images,
+        // as it would have there); otherwise it may be a unicode name.
+        let offered_png = emoji_path.is_file()
+            && (!args.validate_images || is_png(&emoji_path))
+            && !emoji.contains(char::is_control);
+        if has_png_extension(Path::new(emoji.as_str())) && offered_png {
+            entries.push(png_entry(args, png_names, emoji, &emoji_path, png_icon()));
+        } else if let Some(glyph) = tones::resolve_glyph(unicode_emojis, emoji) {
+            entries.push(entry(&glyph, emoji, emoji));
+        }
+    }
+
+    // with nothing in history yet, --recents-only falls back to the full list
+    if args.recents_only && !entries.is_empty() {
+        if let Some(limit) = args.limit {
+            entries.truncate(limit);
+        }
+        return entries;
+    }
+
+    for emoji in png_emojis {
+        let name = png_emoji_name(expanded_png_emojis_path, emoji);
+        entries.push(png_entry(args, png_names, &name, emoji, png_icon()));
+    }
+
+    let history_set: HashSet<&str> = sorted_history.iter().map(|key| key.as_str()).collect();
+    let mut remaining_emojis: Vec<(&String, &String)> = unicode_emojis
+        .iter()
+        .filter(|(key, _)| !history_set.contains(key.as_str()))
+        .collect();
+    match args.sort_all {
+        SortOrder::Name => remaining_emojis.sort_by(|a, b| a.0.cmp(b.0)),
+        // the glyph's codepoints compare in the same order as its UTF-8 bytes
+        SortOrder::Codepoint => remaining_emojis.sort_by(|a, b| (a.1, a.0).cmp(&(b.1, b.0))),
+    }
+
+    for (emoji, value) in remaining_emojis {
+        if args.limit.is_some_and(|limit| entries.len() >= limit) {
+            break;
+        }
+        entries.push(entry(value, emoji, emoji));
+        if args.expand_tones {
+            for (name, glyph) in tones::toned_variants(emoji, value) {
+                if !history_set.contains(name.as_str()) {
+                    entries.push(entry(&glyph, &name, emoji));
+                }
+            }
+        }
+    }
+
+    if let Some(limit) = args.limit {
+        entries.truncate(limit);
+    }
+    entries
+}
+
+// Keywords, the codepoints with --show-codepoints, the description with --search-field and the
+// group with --search-category go in a third field after the glyph and the name, which
+// parse_selection ignores. None of the supported pickers can hide part of a line, so they're
+// shown, but they let the fuzzy matcher find 💰 by "money".
+fn unicode_entry(
+    args: &Args,
+    glyph: &str,
+    name: &str,
+    keywords: Option<&String>,
+    description: Option<&String>,
+    group: Option<&String>,
+) -> String {
+    // longer text is a kaomoji or snippet rather than an emoji
+    let codepoints = (args.show_codepoints && glyph.chars().count() <= MAX_CODEPOINTS_SHOWN)
+        .then(|| codepoints(glyph));
+    // pickers read one entry per line, so multi-line snippets are shown on a single line
+    // nor can the \0 and \x1f of the icon protocol appear in it
+    let glyph = glyph
+        .replace('\n', "↵")
+        .replace([FIELD_SEPARATOR, '\0', '\x1f'], " ");
+    // load_unicode_emojis already cleans up names, this keeps the fields apart regardless
+    let name = name.replace(char::is_control, " ");
+    let entry = match args.layout {
+        Layout::GlyphFirst => format!("{glyph}{FIELD_SEPARATOR}{name}"),
+        Layout::NameFirst => format!("{name}{FIELD_SEPARATOR}{glyph}"),
+    };
+    let extra: Vec<&str> = codepoints
+        .iter()
+        .chain(keywords)
+        .chain(description)
+        .chain(group)
+        .map(String::as_str)
+        .collect();
+    if extra.is_empty() {
+        return entry;
+    }
+    // keywords and descriptions come straight from the emojis file
+    let extra = extra.join(" ").replace(|c: char| c.is_control(), " ");
+    format!("{entry}{FIELD_SEPARATOR}{extra}")
+}
+
+// "U+1F44D U+1F3FD" for 👍🏽.
+fn codepoints(glyph: &str) -> String {
+    let codepoints: Vec<String> = glyph
+        .chars()
+        .map(|c| format!("U+{:04X}", c as u32))
+        .collect();
+    codepoints.join(" ")
+}
+
+// Labelled with the name names.json gives the file, if any; the file stays the history key.
+// Without `icon` (past --max-icons) it's the label alone.
+fn png_entry(
+    args: &Args,
+    png_names: &HashMap<String, String>,
+    file_name: &str,
+    path: &Path,
+    icon: bool,
+) -> String {
+    let label = png_names.get(file_name).map_or(file_name, String::as_str);
+    with_icon(args, label.to_string(), path.to_str().filter(|_| icon))
+}
+
+// Adds the icon protocol suffix for pickers that show icons. Otherwise the label alone is still
+// selectable. Control characters in either part would break the protocol, so they go without.
+fn with_icon(args: &Args, label: String, icon: Option<&str>) -> String {
+    let plain = |text: &str| !text.contains(char::is_control);
+    match icon {
+        Some(icon)
+            if args.picker.supports_icons && !args.no_icons && plain(icon) && plain(&label) =>
+        {
+            format!("{label}\0icon\x1f{icon}")
+        }
+        _ => label,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_args(flags: &[&str]) -> Args {
+        let config = Config::parse("").unwrap();
+        parse_args_from(&config, flags.iter().map(|flag| flag.to_string()), |_| None).unwrap()
+    }
+
+    // picker_entries with no emoji details or png names
+    fn list_entries(
+        args: &Args,
+        unicode_emojis: &HashMap<String, String>,
+        png_emojis: &Vec<PathBuf>,
+        sorted_history: &Vec<&String>,
+        expanded_png_emojis_path: &Path,
+    ) -> Vec<String> {
+        picker_entries(
+            args,
+            unicode_emojis,
+            &EmojiDetails::default(),
+            png_emojis,
+            &HashMap::new(),
+            sorted_history,
+            expanded_png_emojis_path,
+        )
+    }
+
+    fn emojis(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|&(name, glyph)| (name.to_string(), glyph.to_string()))
+            .collect()
+    }
+
+    fn parse(
+        output: &str,
+        args: &Args,
+        unicode_emojis: &HashMap<String, String>,
+        png_folder: &Path,
+    ) -> Selection {
+        parse_selection(output, args, unicode_emojis, &HashMap::new(), png_folder)
+            .unwrap()
+            .expect("a selection")
+    }
+
+    // A folder of its own under the temp dir, emptied first.
+    fn temp_folder(name: &str) -> PathBuf {
+        let folder = env::temp_dir().join(format!("rustoji-test-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    #[test]
+    fn picker_output_keeps_meaningful_whitespace() {
+        assert_eq!(strip_line_ending("😀\tgrinning\n"), "😀\tgrinning");
+        // a whitespace glyph, and a kaomoji whose name ends in a space
+        assert_eq!(strip_line_ending(" \tspace\n"), " \tspace");
+        assert_eq!(
+            strip_line_ending("¯\\_(ツ)_/¯ \tshrug \n"),
+            "¯\\_(ツ)_/¯ \tshrug "
+        );
+        assert_eq!(strip_line_ending("  \n"), "  ");
+        assert_eq!(strip_line_ending("no newline "), "no newline ");
+    }
+
+    #[test]
+    fn png_and_unicode_emoji_sharing_a_name() {
+        let folder = temp_folder("shared-name");
+        fs::write(folder.join("party.png"), b"\x89PNG").unwrap();
+        let args = test_args(&[]);
+        let unicode_emojis = emojis(&[("party", "🎉"), ("party.png", "🥳")]);
+        // a unicode entry whose name looks like a file stays a unicode pick
+        let selection = parse("🥳\tparty.png", &args, &unicode_emojis, &folder);
+        assert!(matches!(selection, Selection::Unicode { ref glyph, .. } if glyph == "🥳"));
+        let selection = parse("🎉\tparty", &args, &unicode_emojis, &folder);
+        assert!(matches!(selection, Selection::Unicode { ref glyph, .. } if glyph == "🎉"));
+        let selection = parse("party.png", &args, &unicode_emojis, &folder);
+        assert!(matches!(selection, Selection::Png(ref file) if file == "party.png"));
+
+        // the unicode "party" in history doesn't hide party.png, but the png's own key does
+        let unicode_key = "party".to_string();
+        let png_key = "party.png".to_string();
+        let listed =
+            collect_png_emojis_and_filter(&folder, &vec![&unicode_key], None, &test_args(&[]))
+                .unwrap();
+        assert_eq!(listed, [folder.join("party.png")]);
+        let listed =
+            collect_png_emojis_and_filter(&folder, &vec![&png_key], None, &test_args(&[])).unwrap();
+        assert!(listed.is_empty());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn zwj_sequences_are_copied_whole() {
+        let args = test_args(&[]);
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        let technologist = "\u{1F9D1}\u{200D}\u{1F4BB}";
+        let unicode_emojis = emojis(&[("family", family), ("technologist", technologist)]);
+        let toned = "\u{1F9D1}\u{1F3FD}\u{200D}\u{1F4BB}";
+        for (line, glyph) in [
+            (format!("{family}\tfamily\n"), family),
+            (format!("{toned}\ttechnologist: medium skin tone\n"), toned),
+        ] {
+            let output = strip_line_ending(&line);
+            let selection = parse(output, &args, &unicode_emojis, Path::new("/nonexistent"));
+            assert_eq!(selection.text(), glyph);
+        }
+    }
+
+    #[test]
+    fn duplicate_names_keep_both_emojis() {
+        let folder = temp_folder("duplicates");
+        let map_file = folder.join("map.json");
+        fs::write(
+            &map_file,
+            r#"{"heart": "❤️", "heart": "♥️", "star": "⭐", "star": "⭐"}"#,
+        )
+        .unwrap();
+        let list_file = folder.join("list.json");
+        fs::write(
+            &list_file,
+            r#"[{"emoji": "❤️", "name": "heart"}, {"emoji": "♥️", "name": "heart"}]"#,
+        )
+        .unwrap();
+
+        for path in [&map_file, &list_file] {
+            let (unicode_emojis, _) = load_unicode_emojis(path, false).unwrap();
+            assert_eq!(unicode_emojis["heart"], "❤️", "{path:?}");
+            assert_eq!(unicode_emojis["heart (U+2665 U+FE0F)"], "♥️", "{path:?}");
+        }
+        // the same glyph twice is no duplicate
+        let (unicode_emojis, _) = load_unicode_emojis(&map_file, false).unwrap();
+        assert_eq!(unicode_emojis.len(), 3);
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn broken_png_in_history_fails_validate_images() {
+        let folder = temp_folder("validate_history");
+        fs::create_dir(folder.join("cats")).unwrap();
+        fs::write(folder.join("cats/good.png"), b"\x89PNG\r\n\x1a\nrest").unwrap();
+        fs::write(folder.join("broken.png"), b"not a png").unwrap();
+        let good = "cats/good.png".to_string();
+        let broken = "broken.png".to_string();
+        let history = vec![&good, &broken];
+
+        for (flags, expected) in [
+            (&[][..], vec!["cats/good.png", "broken.png"]),
+            (&["--validate-images"][..], vec!["cats/good.png"]),
+        ] {
+            let args = test_args(flags);
+            let png_emojis = collect_png_emojis_and_filter(&folder, &history, None, &args).unwrap();
+            // history's pngs are left out here and listed from history instead
+            assert!(png_emojis.is_empty(), "{flags:?}");
+            let entries = list_entries(&args, &HashMap::new(), &png_emojis, &history, &folder);
+            let names: Vec<&str> = entries
+                .iter()
+                .map(|entry| entry.split('\0').next().unwrap())
+                .collect();
+            assert_eq!(names, expected, "{flags:?}");
+        }
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn toned_picks_have_their_own_history_key() {
+        let unicode_emojis = emojis(&[("+1", "\u{1F44D}")]);
+        let toned = "\u{1F44D}\u{1F3FD}";
+        let args = test_args(&["--expand-tones"]);
+        let entries = list_entries(
+            &args,
+            &unicode_emojis,
+            &Vec::new(),
+            &Vec::new(),
+            Path::new("/nonexistent"),
+        );
+        let mut history = history::History::new();
+        for name in ["+1", "+1: medium skin tone"] {
+            let entry = entries
+                .iter()
+                .find(|entry| entry.ends_with(&format!("\t{name}")))
+                .unwrap();
+            let selection = parse(entry, &args, &unicode_emojis, Path::new("/nonexistent"));
+            history::record(
+                &mut history,
+                selection.history_key().unwrap().to_string(),
+                None,
+            );
+        }
+        assert_eq!(history.len(), 2);
+
+        // both come back from history, without --expand-tones too
+        let args = test_args(&[]);
+        let plain = "+1".to_string();
+        let toned_name = "+1: medium skin tone".to_string();
+        let entries = list_entries(
+            &args,
+            &unicode_emojis,
+            &Vec::new(),
+            &vec![&toned_name, &plain],
+            Path::new("/nonexistent"),
+        );
+        assert_eq!(
+            entries,
+            [
+                format!("{toned}\t{toned_name}"),
+                format!("\u{1F44D}\t{plain}")
+            ]
+        );
+    }
+
+    #[test]
+    fn counter_text_stays_out_of_the_selection() {
+        // fuzzel draws its matches/total counter in the prompt line, so stdout is the entry alone
+        assert_eq!(
+            picker::find("fuzzel").unwrap().counter_flag,
+            Some("--counter")
+        );
+        let args = test_args(&[]);
+        let unicode_emojis = emojis(&[("100", "\u{1F4AF}"), ("1st place medal", "\u{1F947}")]);
+        let entries = list_entries(
+            &args,
+            &unicode_emojis,
+            &Vec::new(),
+            &Vec::new(),
+            Path::new("/nonexistent"),
+        );
+        for entry in &entries {
+            let output = format!("{entry}\n");
+            let selection = parse(
+                strip_line_ending(&output),
+                &args,
+                &unicode_emojis,
+                Path::new("/nonexistent"),
+            );
+            let (glyph, name) = entry.split_once('\t').unwrap();
+            assert_eq!(selection.text(), glyph);
+            assert_eq!(selection.history_key(), Some(name));
+        }
+        assert_eq!(entries.len(), 2);
+    }
+
+    #[test]
+    fn png_extension_ignores_case() {
+        assert!(has_png_extension(Path::new("blob.png")));
+        assert!(has_png_extension(Path::new("blob.PNG")));
+        assert!(has_png_extension(Path::new("dir/blob.Png")));
+        assert!(!has_png_extension(Path::new(".PNG")));
+        assert!(!has_png_extension(Path::new("blob.png.txt")));
+        assert!(!has_png_extension(Path::new("blob")));
+    }
+
+    #[test]
+    fn uppercase_png_files_are_listed_and_copied() {
+        let folder = temp_folder("uppercase_png");
+        fs::write(folder.join("Cat.PNG"), b"\x89PNG\r\n\x1a\n").unwrap();
+        let args = test_args(&[]);
+        let png_emojis = collect_png_emojis_and_filter(&folder, &Vec::new(), None, &args).unwrap();
+        assert_eq!(png_emojis, [folder.join("Cat.PNG")]);
+        let selection = parse("Cat.PNG", &args, &HashMap::new(), &folder);
+        assert!(matches!(selection, Selection::Png(ref file_name) if file_name == "Cat.PNG"));
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn aliases_resolve_to_the_canonical_name() {
+        let folder = temp_folder("aliases");
+        let path = folder.join("emojis.json");
+        fs::write(
+            &path,
+            r#"[{"emoji": "👍", "name": "thumbs up", "aliases": ["+1", "thumbsup"]}]"#,
+        )
+        .unwrap();
+        let (unicode_emojis, details) = load_unicode_emojis(&path, false).unwrap();
+        assert_eq!(unicode_emojis["+1"], "👍");
+        let aliases = &details.aliases;
+        assert_eq!(canonical_name(aliases, "+1"), "thumbs up");
+        assert_eq!(
+            canonical_name(aliases, "thumbsup: medium skin tone"),
+            "thumbs up: medium skin tone"
+        );
+        assert_eq!(canonical_name(aliases, "thumbs up"), "thumbs up");
+        assert_eq!(canonical_name(aliases, "grinning"), "grinning");
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn pack_manifests_name_the_files() {
+        let folder = temp_folder("pack-names");
+        fs::write(
+            folder.join("manifest.json"),
+            r#"[{"id": "396521773144866826", "name": "blobcat"}, {"file": "a.webp", "name": ":party:"}]"#,
+        )
+        .unwrap();
+        let names = load_pack_names(&folder).unwrap();
+        assert_eq!(names["396521773144866826"], "blobcat");
+        assert_eq!(names["a.webp"], "party");
+
+        fs::remove_file(folder.join("manifest.json")).unwrap();
+        fs::write(
+            folder.join("names.json"),
+            r#"{"pp_01.png": "party parrot", "x.png": "../up"}"#,
+        )
+        .unwrap();
+        let names = load_pack_names(&folder).unwrap();
+        assert_eq!(names["pp_01.png"], "party parrot");
+        assert!(!names.contains_key("x.png"));
+
+        fs::write(folder.join("names.json"), r#""blobcat""#).unwrap();
+        assert!(load_pack_names(&folder).is_err());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn packs_take_webp_and_gif_too() {
+        assert!(is_pack_image(Path::new("blob.WEBP")));
+        assert!(is_pack_image(Path::new("blob.gif")));
+        assert!(is_pack_image(Path::new("blob.png")));
+        assert!(!is_pack_image(Path::new("manifest.json")));
+    }
+
+    fn program_and_args(command: &Command) -> Vec<String> {
+        std::iter::once(command.get_program())
+            .chain(command.get_args())
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn copy_commands_per_selection() {
+        let selections: [(&[&str], &[&[&str]]); 3] = [
+            (&["--wayland"], &[&[]]),
+            (&["--wayland", "--selection", "primary"], &[&["--primary"]]),
+            (
+                &["--wayland", "--selection", "both"],
+                &[&[], &["--primary"]],
+            ),
+        ];
+        let kinds: [(&[u8], &str, &[&str], bool); 3] = [
+            (
+                b"-_-",
+                "text/plain",
+                &["-t", "text/plain", "--", "-_-"],
+                false,
+            ),
+            (b"\x89PNG", "image/png", &["-t", "image/png"], true),
+            (
+                b"file:///emojis/blob.png",
+                "text/uri-list",
+                &["-t", "text/uri-list", "--", "file:///emojis/blob.png"],
+                false,
+            ),
+        ];
+        for (flags, selection_args) in selections {
+            let args = test_args(flags);
+            for (data, mime, data_args, stdin) in kinds {
+                let commands = clipboard_commands(&args, data, mime);
+                let commands: Vec<(Vec<String>, bool)> = commands
+                    .iter()
+                    .map(|(command, on_stdin)| (program_and_args(command), *on_stdin))
+                    .collect();
+                let expected: Vec<(Vec<String>, bool)> = selection_args
+                    .iter()
+                    .map(|selection_args| {
+                        let words = ["wl-copy"].iter().chain(*selection_args).chain(data_args);
+                        (words.map(|word| word.to_string()).collect(), stdin)
+                    })
+                    .collect();
+                assert_eq!(commands, expected, "{flags:?} {mime}");
+            }
+        }
+    }
+
+    #[test]
+    fn picker_output_loses_a_trailing_crlf() {
+        assert_eq!(strip_line_ending("😀\tgrinning\r\n"), "😀\tgrinning");
+        assert_eq!(strip_line_ending("blob.png\r\n"), "blob.png");
+        // only one line ending goes
+        assert_eq!(strip_line_ending("a\r\n\n"), "a\r\n");
+
+        let folder = temp_folder("crlf");
+        fs::write(folder.join("blob.png"), b"\x89PNG").unwrap();
+        let args = test_args(&[]);
+        let selection = parse(
+            strip_line_ending("blob.png\r\n"),
+            &args,
+            &HashMap::new(),
+            &folder,
+        );
+        assert!(matches!(selection, Selection::Png(ref file_name) if file_name == "blob.png"));
+        let unicode_emojis = emojis(&[("grinning", "😀")]);
+        let selection = parse(
+            strip_line_ending("😀\tgrinning\r\n"),
+            &args,
+            &unicode_emojis,
+            &folder,
+        );
+        assert_eq!(selection.history_key(), Some("grinning"));
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn emojis_file_download_creates_its_folder() {
+        use std::os::unix::fs::PermissionsExt;
+        let folder = temp_folder("nested-download");
+        // writes {} to the path after -O, like wget would write the download
+        let wget = folder.join("wget");
+        fs::write(&wget, "#!/bin/sh\necho '{}' > \"$3\"\n").unwrap();
+        fs::set_permissions(&wget, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let path = folder.join("a/b/emojis.json");
+        download_unicode_emojis_file(wget.to_str().unwrap(), &path, None).unwrap();
+        assert!(folder.join("a/b").is_dir());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{}\n");
+        assert!(db_meta_path(&path).is_file());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn x11_copies_go_through_xclip_stdin() {
+        let args = test_args(&["--x11", "--selection", "both"]);
+        for mime in ["text/plain", "image/png"] {
+            let commands: Vec<(Vec<String>, bool)> = clipboard_commands(&args, b"-_-", mime)
+                .iter()
+                .map(|(command, on_stdin)| (program_and_args(command), *on_stdin))
+                .collect();
+            let expected: Vec<(Vec<String>, bool)> = ["clipboard", "primary"]
+                .iter()
+                .map(|selection| {
+                    let words = ["xclip", "-selection", selection, "-t", mime];
+                    (words.map(|word| word.to_string()).to_vec(), true)
+                })
+                .collect();
+            assert_eq!(commands, expected, "{mime}");
+        }
+    }
+
+    #[test]
+    fn control_characters_stay_out_of_entries() {
+        let args = test_args(&["fuzzel"]);
+        for name in ["unit\x1fseparator", "a\ttab", "nul\0byte"] {
+            let entry = unicode_entry(&args, "😀", name, None, None, None);
+            assert_eq!(entry.matches(FIELD_SEPARATOR).count(), 1, "{entry:?}");
+            assert!(!entry.contains(['\0', '\x1f']), "{entry:?}");
+        }
+        let entry = unicode_entry(&args, "a\x1fb\tc\nd", "odd", None, None, None);
+        assert_eq!(entry, "a b c↵d\todd");
+
+        let icon = "/emojis/blob.png";
+        assert_eq!(
+            with_icon(&args, "blob.png".into(), Some(icon)),
+            "blob.png\0icon\x1f/emojis/blob.png"
+        );
+        assert_eq!(
+            with_icon(&args, "bl\x1fob.png".into(), Some(icon)),
+            "bl\x1fob.png"
+        );
+        assert_eq!(
+            with_icon(&args, "bl\tob.png".into(), Some(icon)),
+            "bl\tob.png"
+        );
+        assert_eq!(
+            with_icon(&args, "blob.png".into(), Some("/emo\x1fjis/blob.png")),
+            "blob.png"
+        );
+    }
+
+    #[test]
+    fn nul_bytes_are_piped_to_wl_copy() {
+        let commands = clipboard_commands(&test_args(&["--wayland"]), b"a\0b", "text/plain");
+        let [(command, true)] = &commands[..] else {
+            panic!("one command on stdin");
+        };
+        assert_eq!(program_and_args(command), ["wl-copy", "-t", "text/plain"]);
+    }
+
+    #[test]
+    fn copy_mode_from_the_environment_yields_to_arguments() {
+        let config = Config::parse("").unwrap();
+        let copy_path = |copy_mode: &str, flags: &[&str]| {
+            let raw_args = flags.iter().map(|flag| flag.to_string());
+            let env_var = |name: &str| (name == "RUSTOJI_COPY_MODE").then(|| copy_mode.to_string());
+            parse_args_from(&config, raw_args, env_var)
+                .unwrap()
+                .copy_mode
+                == CopyMode::Path
+        };
+        assert!(copy_path("path", &[]));
+        assert!(!copy_path("image", &[]));
+        // an unknown value keeps the default, a path
+        assert!(copy_path("bogus", &[]));
+        assert!(copy_path("image", &["fuzzel", "true"]));
+        assert!(!copy_path("path", &["fuzzel", "false"]));
+        assert!(!copy_path("path", &["--copy", "image"]));
+    }
+
+    #[test]
+    fn png_pretty_names_lead_back_to_the_file() {
+        let folder = temp_folder("png-names");
+        fs::write(folder.join("pp_01.png"), b"\x89PNG").unwrap();
+        fs::write(
+            folder.join("names.json"),
+            r#"{"pp_01.png": "Party Parrot"}"#,
+        )
+        .unwrap();
+        let png_names = load_png_names(&folder).unwrap();
+        let args = test_args(&["fuzzel"]);
+        let png_emojis = collect_png_emojis_and_filter(&folder, &Vec::new(), None, &args).unwrap();
+        let entries = picker_entries(
+            &args,
+            &HashMap::new(),
+            &EmojiDetails::default(),
+            &png_emojis,
+            &png_names,
+            &Vec::new(),
+            &folder,
+        );
+        let icon = folder.join("pp_01.png");
+        assert_eq!(
+            entries,
+            [format!("Party Parrot\0icon\x1f{}", icon.display())]
+        );
+
+        let selected = select_entry(
+            &args,
+            &entries,
+            "party parr",
+            &history::History::new(),
+            &png_names,
+        )
+        .unwrap();
+        for output in ["Party Parrot", selected.as_str()] {
+            let selection = parse_selection(output, &args, &HashMap::new(), &png_names, &folder)
+                .unwrap()
+                .expect("a selection");
+            assert!(matches!(selection, Selection::Png(ref file_name) if file_name == "pp_01.png"));
+        }
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("30m").unwrap(), Duration::from_secs(30 * 60));
+        assert_eq!(
+            parse_duration("7").unwrap(),
+            Duration::from_secs(7 * 24 * 60 * 60)
+        );
+        assert!(parse_duration("12x").is_err());
+        assert!(parse_duration(&format!("{}d", u64::MAX / 2)).is_err());
+    }
+
+    #[test]
+    fn typed_text_with_a_tab_is_free_text() {
+        let args = test_args(&[]);
+        let unicode_emojis = emojis(&[("grinning", "😀")]);
+        let selection = parse("a\tb", &args, &unicode_emojis, Path::new("/nonexistent"));
+        assert!(matches!(selection, Selection::Text(ref text) if text == "a\tb"));
+    }
+
+    #[test]
+    fn name_first_layout_reads_the_first_field() {
+        let args = test_args(&["--layout", "name-first"]);
+        let unicode_emojis = emojis(&[("grinning", "😀")]);
+        let output = "grinning\t😀\tsmile happy";
+        let selection = parse(output, &args, &unicode_emojis, Path::new("/nonexistent"));
+        assert!(matches!(selection, Selection::Unicode { ref name, .. } if name == "grinning"));
+        // the same line read glyph-first names no known emoji
+        let selection = parse(
+            output,
+            &test_args(&[]),
+            &unicode_emojis,
+            Path::new("/nonexistent"),
+        );
+        assert!(matches!(selection, Selection::Text(_)));
+    }
+
+    #[test]
+    fn the_session_decides_the_display_server() {
+        let session = |vars: &'static [&str]| move |name: &str| vars.contains(&name);
+        let cases: [(DisplayServer, &[&str], DisplayServer); 6] = [
+            (
+                DisplayServer::Auto,
+                &["WAYLAND_DISPLAY"],
+                DisplayServer::Wayland,
+            ),
+            (
+                DisplayServer::Auto,
+                &["WAYLAND_DISPLAY", "DISPLAY"],
+                DisplayServer::Wayland,
+            ),
+            (DisplayServer::Auto, &["DISPLAY"], DisplayServer::X11),
+            (DisplayServer::Auto, &[], DisplayServer::Auto),
+            (DisplayServer::X11, &["WAYLAND_DISPLAY"], DisplayServer::X11),
+            (DisplayServer::Wayland, &[], DisplayServer::Wayland),
+        ];
+        for (forced, vars, expected) in cases {
+            assert!(
+                resolve_display_server(forced, session(vars)) == expected,
+                "{vars:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn history_boosts_png_names_by_their_file() {
+        let png_names = HashMap::from([
+            ("pp_01.png".to_string(), "Party Parrot".to_string()),
+            ("pc_01.png".to_string(), "Party Cat".to_string()),
+        ]);
+        let entries = ["Party Parrot".to_string(), "Party Cat".to_string()];
+        let args = test_args(&[]);
+        let history = history::History::new();
+        assert!(select_entry(&args, &entries, "party", &history, &png_names).is_err());
+
+        let mut history = history::History::new();
+        history::record(&mut history, "pc_01.png".to_string(), None);
+        let selected = select_entry(&args, &entries, "party", &history, &png_names).unwrap();
+        assert_eq!(selected, "Party Cat");
+    }
+
+    #[test]
+    fn select_keeps_dots_in_png_names() {
+        let png_names = HashMap::from([("pet.png".to_string(), "Cat vs. Dog".to_string())]);
+        let entries = ["Cat vs. Dog".to_string(), "dog.png".to_string()];
+        let args = test_args(&[]);
+        let history = history::History::new();
+        let select = |query| select_entry(&args, &entries, query, &history, &png_names).unwrap();
+        assert_eq!(select("cat vs. dog"), "Cat vs. Dog");
+        assert_eq!(select("dog"), "dog.png");
+    }
+
+    #[test]
+    fn clipboard_text_is_what_gets_copied() {
+        let selection = Selection::Unicode {
+            glyph: "👍".to_string(),
+            name: "thumbs up".to_string(),
+        };
+        let folder = Path::new("/emojis");
+        let text =
+            |flags: &[&str]| clipboard_text(&selection, Some("(y)"), folder, &test_args(flags));
+        assert_eq!(
+            text(&[
+                "--prefix",
+                "[",
+                "--suffix",
+                "]",
+                "--repeat",
+                "2",
+                "--with-name"
+            ]),
+            Some(("[👍👍] (y) thumbs up".to_string(), "text/plain"))
+        );
+        assert_eq!(
+            text(&["--copy-name", "--newline"]),
+            Some(("thumbs up\n".to_string(), "text/plain"))
+        );
+        assert_eq!(
+            text(&["--html", "--prefix", "<", "--suffix", ">"]),
+            Some(("<span>&lt;👍&gt; (y)</span>".to_string(), "text/html"))
+        );
+        let png = Selection::Png("blob.png".to_string());
+        assert_eq!(clipboard_text(&png, None, folder, &test_args(&[])), None);
+    }
+
+    #[test]
+    fn copy_and_type_refuses_html() {
+        let config = Config::parse("").unwrap();
+        let flags = ["--copy-and-type", "--html"].map(String::from);
+        assert!(parse_args_from(&config, flags.into_iter(), |_| None).is_err());
+        assert!(test_args(&["--copy-and-type", "--prefix", "`"]).copy_and_type);
+    }
+
+    #[test]
+    fn corrupt_history_keeps_its_file_name_in_the_backup() {
+        let folder = temp_folder("corrupt-history");
+        for file_name in ["history.json", "picks.v2.json", "history"] {
+            let path = folder.join(file_name);
+            fs::write(&path, "{").unwrap();
+            assert!(history::load(&path).unwrap().is_empty());
+            assert!(!path.exists());
+            assert!(
+                folder.join(format!("{file_name}.bak")).is_file(),
+                "{file_name}"
+            );
+        }
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn unreadable_subfolders_are_skipped() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let folder = temp_folder("unreadable-subfolder");
+        fs::write(folder.join("blob.png"), b"\x89PNG").unwrap();
+        let locked = folder.join("locked");
+        fs::create_dir(&locked).unwrap();
+        fs::write(locked.join("hidden.png"), b"\x89PNG").unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+
+        let mut found = Vec::new();
+        let collected = collect_png_files(&folder, true, &mut HashSet::new(), &mut found);
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+        collected.unwrap();
+        assert!(found.contains(&folder.join("blob.png")));
+
+        let missing = folder.join("missing");
+        assert!(collect_png_files(&missing, true, &mut HashSet::new(), &mut Vec::new()).is_err());
+        fs::remove_dir_all(&folder).unwrap();
+    }
 }
@@ -0,0 +1,116 @@
+// Everything rustoji needs to know about a picker lives in its entry here, so supporting
+// another one is a matter of adding a row.
+pub struct Picker {
+    pub name: &'static str,
+    pub base_args: &'static [&'static str], // always passed, e.g. fuzzel's dmenu mode
+    pub supports_icons: bool,               // renders the `\0icon\x1f<path>` entry suffix
+    pub counter_flag: Option<&'static str>, // shows a matches/total counter
+    pub prompt_flag: Option<&'static str>,  // takes the prompt text as the next argument
+    pub query_flag: Option<&'static str>,   // takes an initial filter as the next argument
+    pub width_flag: Option<&'static str>,   // window width in characters
+    pub lines_flag: Option<&'static str>,   // number of entries shown at once
+    pub anchor_flag: Option<&'static str>,  // window position, e.g. "top" or "center"
+    pub dpi_aware_flag: Option<&'static str>,
+    pub cancel_codes: &'static [i32], // exit codes for closing it without a pick, e.g. with Escape
+}
+
+// The first one is the default.
+pub const PICKERS: [Picker; 3] = [
+    Picker {
+        name: "fuzzel",
+        base_args: &["--dmenu"],
+        supports_icons: true,
+        counter_flag: Some("--counter"),
+        prompt_flag: Some("--prompt"),
+        query_flag: Some("--search"),
+        dpi_aware_flag: Some("--dpi-aware"),
+        width_flag: Some("--width"),
+        lines_flag: Some("--lines"),
+        anchor_flag: Some("--anchor"),
+        // 0 with the pick (or the typed text), 1 when cancelled; 10-19 are its --custom-key exits
+        cancel_codes: &[1],
+    },
+    Picker {
+        name: "bemenu",
+        base_args: &[],
+        supports_icons: false,
+        counter_flag: None,
+        prompt_flag: Some("-p"),
+        query_flag: Some("--filter"),
+        dpi_aware_flag: None,
+        width_flag: None,
+        lines_flag: Some("-l"),
+        anchor_flag: None,
+        // 0 with the pick, 1 on Escape
+        cancel_codes: &[1],
+    },
+    Picker {
+        name: "wmenu",
+        base_args: &[],
+        supports_icons: false,
+        counter_flag: None,
+        prompt_flag: Some("-p"),
+        query_flag: None,
+        dpi_aware_flag: None,
+        width_flag: None,
+        lines_flag: Some("-l"),
+        anchor_flag: None,
+        // 0 with the pick, 1 on Escape or Ctrl-C
+        cancel_codes: &[1],
+    },
+];
+
+impl Picker {
+    // What rustoji can use, named after the options that map to it.
+    pub fn features(&self) -> Vec<&'static str> {
+        let flags = [
+            ("counter", self.counter_flag),
+            ("prompt", self.prompt_flag),
+            ("query", self.query_flag),
+            ("width", self.width_flag),
+            ("lines", self.lines_flag),
+            ("anchor", self.anchor_flag),
+            ("dpi-aware", self.dpi_aware_flag),
+        ];
+        let icons = self.supports_icons.then_some("icons");
+        icons
+            .into_iter()
+            .chain(
+                flags
+                    .into_iter()
+                    .filter_map(|(feature, flag)| flag.map(|_| feature)),
+            )
+            .collect()
+    }
+}
+
+pub fn find(name: &str) -> Option<&'static Picker> {
+    PICKERS.iter().find(|picker| picker.name == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn every_picker_is_found_by_its_own_name() {
+        let mut names = HashSet::new();
+        for picker in &PICKERS {
+            assert!(names.insert(picker.name), "{} is listed twice", picker.name);
+            let found = find(picker.name).unwrap();
+            assert_eq!(found.name, picker.name);
+            assert_eq!(found.base_args, picker.base_args);
+        }
+        assert!(find("dmenu").is_none());
+    }
+
+    #[test]
+    fn every_picker_has_a_cancel_code() {
+        for picker in &PICKERS {
+            assert!(!picker.cancel_codes.is_empty(), "{}", picker.name);
+            // 0 is a pick, never a cancel
+            assert!(!picker.cancel_codes.contains(&0), "{}", picker.name);
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+
+// Fitzpatrick skin tone modifiers, with the CLDR names used to label toned entries.
+pub const SKIN_TONES: [(&str, char); 5] = [
+    ("light skin tone", '\u{1F3FB}'),
+    ("medium-light skin tone", '\u{1F3FC}'),
+    ("medium skin tone", '\u{1F3FD}'),
+    ("medium-dark skin tone", '\u{1F3FE}'),
+    ("dark skin tone", '\u{1F3FF}'),
+];
+
+// Codepoint ranges with the Unicode Emoji_Modifier_Base property (emoji-data.txt).
+const MODIFIER_BASES: [(u32, u32); 40] = [
+    (0x261D, 0x261D),
+    (0x26F9, 0x26F9),
+    (0x270A, 0x270D),
+    (0x1F385, 0x1F385),
+    (0x1F3C2, 0x1F3C4),
+    (0x1F3C7, 0x1F3C7),
+    (0x1F3CA, 0x1F3CC),
+    (0x1F442, 0x1F443),
+    (0x1F446, 0x1F450),
+    (0x1F466, 0x1F478),
+    (0x1F47C, 0x1F47C),
+    (0x1F481, 0x1F483),
+    (0x1F485, 0x1F487),
+    (0x1F48F, 0x1F48F),
+    (0x1F491, 0x1F491),
+    (0x1F4AA, 0x1F4AA),
+    (0x1F574, 0x1F575),
+    (0x1F57A, 0x1F57A),
+    (0x1F590, 0x1F590),
+    (0x1F595, 0x1F596),
+    (0x1F645, 0x1F647),
+    (0x1F64B, 0x1F64F),
+    (0x1F6A3, 0x1F6A3),
+    (0x1F6B4, 0x1F6B6),
+    (0x1F6C0, 0x1F6C0),
+    (0x1F6CC, 0x1F6CC),
+    (0x1F90C, 0x1F90C),
+    (0x1F90F, 0x1F90F),
+    (0x1F918, 0x1F91F),
+    (0x1F926, 0x1F926),
+    (0x1F930, 0x1F939),
+    (0x1F93C, 0x1F93E),
+    (0x1F977, 0x1F977),
+    (0x1F9B5, 0x1F9B6),
+    (0x1F9B8, 0x1F9B9),
+    (0x1F9BB, 0x1F9BB),
+    (0x1F9CD, 0x1F9CF),
+    (0x1F9D1, 0x1F9DD),
+    (0x1FAC3, 0x1FAC5),
+    (0x1FAF0, 0x1FAF8),
+];
+
+// Only sequences with a single person can be toned by modifying their first codepoint;
+// toning one member of a ZWJ family or couple would produce a mixed, unsupported sequence.
+pub fn is_modifiable(glyph: &str) -> bool {
+    let Some(first) = glyph.chars().next() else {
+        return false;
+    };
+    let already_toned = glyph
+        .chars()
+        .any(|c| SKIN_TONES.iter().any(|&(_, tone)| tone == c));
+    let modifier_bases = glyph.chars().filter(|&c| is_modifier_base(c)).count();
+    !already_toned && is_modifier_base(first) && modifier_bases == 1
+}
+
+fn is_modifier_base(c: char) -> bool {
+    MODIFIER_BASES
+        .iter()
+        .any(|&(start, end)| (start..=end).contains(&(c as u32)))
+}
+
+// The modifier goes right after the base codepoint, replacing its emoji presentation selector.
+pub fn with_tone(glyph: &str, tone: char) -> String {
+    let mut chars = glyph.chars();
+    let mut toned: String = chars.next().into_iter().collect();
+    toned.push(tone);
+    let rest = chars.as_str();
+    toned.push_str(rest.strip_prefix('\u{FE0F}').unwrap_or(rest));
+    toned
+}
+
+// Name and glyph of each tone variant, e.g. "thumbsup: medium skin tone" 👍🏽.
+pub fn toned_variants(name: &str, glyph: &str) -> Vec<(String, String)> {
+    if !is_modifiable(glyph) {
+        return Vec::new();
+    }
+    SKIN_TONES
+        .iter()
+        .map(|&(tone_name, tone)| (format!("{name}: {tone_name}"), with_tone(glyph, tone)))
+        .collect()
+}
+
+// Looks up a glyph by name, including the names of tone variants.
+pub fn resolve_glyph(unicode_emojis: &HashMap<String, String>, name: &str) -> Option<String> {
+    if let Some(glyph) = unicode_emojis.get(name) {
+        return Some(glyph.clone());
+    }
+    let (base_name, tone_name) = name.rsplit_once(": ")?;
+    let &(_, tone) = SKIN_TONES.iter().find(|&&(known, _)| known == tone_name)?;
+    let glyph = unicode_emojis.get(base_name)?;
+    is_modifiable(glyph).then(|| with_tone(glyph, tone))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MEDIUM: char = '\u{1F3FD}';
+
+    #[test]
+    fn single_person_zwj_sequences_take_a_tone() {
+        // technologist: person, ZWJ, laptop
+        let technologist = "\u{1F9D1}\u{200D}\u{1F4BB}";
+        assert!(is_modifiable(technologist));
+        assert_eq!(
+            with_tone(technologist, MEDIUM),
+            "\u{1F9D1}\u{1F3FD}\u{200D}\u{1F4BB}"
+        );
+        // woman detective: the presentation selector after the base makes way for the tone, the
+        // one after the female sign stays
+        let detective = "\u{1F575}\u{FE0F}\u{200D}\u{2640}\u{FE0F}";
+        assert!(is_modifiable(detective));
+        assert_eq!(
+            with_tone(detective, MEDIUM),
+            "\u{1F575}\u{1F3FD}\u{200D}\u{2640}\u{FE0F}"
+        );
+    }
+
+    #[test]
+    fn families_and_toned_sequences_dont() {
+        // family: man, woman, girl
+        assert!(!is_modifiable(
+            "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}"
+        ));
+        assert!(!is_modifiable("\u{1F9D1}\u{1F3FD}\u{200D}\u{1F4BB}"));
+        // red heart, no person in it
+        assert!(!is_modifiable("\u{2764}\u{FE0F}"));
+        assert!(!is_modifiable(""));
+    }
+
+    #[test]
+    fn toned_names_resolve() {
+        let unicode_emojis = HashMap::from([
+            (
+                "technologist".to_string(),
+                "\u{1F9D1}\u{200D}\u{1F4BB}".to_string(),
+            ),
+            ("red heart".to_string(), "\u{2764}\u{FE0F}".to_string()),
+        ]);
+        assert_eq!(
+            resolve_glyph(&unicode_emojis, "technologist: medium skin tone").as_deref(),
+            Some("\u{1F9D1}\u{1F3FD}\u{200D}\u{1F4BB}")
+        );
+        assert_eq!(
+            resolve_glyph(&unicode_emojis, "red heart: medium skin tone"),
+            None
+        );
+        assert_eq!(resolve_glyph(&unicode_emojis, "technologist: green"), None);
+    }
+}
@@ -0,0 +1,92 @@
+// Drives a whole pick with stub scripts standing in for the picker and the clipboard: the stub
+// picker records the entries it was given and picks one, and the stub --copy-cmd records what
+// would have been copied.
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn temp_home(name: &str) -> PathBuf {
+    let home = std::env::temp_dir().join(format!("rustoji-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&home);
+    fs::create_dir_all(home.join("bin")).unwrap();
+    home
+}
+
+fn write_script(path: &Path, body: &str) {
+    fs::write(path, format!("#!/bin/sh\n{body}\n")).unwrap();
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+fn rustoji(home: &Path, picked: &str) -> Command {
+    let bin = home.join("bin");
+    write_script(
+        &bin.join("fuzzel"),
+        &format!(
+            "cat > '{}'\nprintf '%s\\n' '{picked}'",
+            home.join("picker_in").display()
+        ),
+    );
+    write_script(
+        &bin.join("copy"),
+        &format!("cat > '{}'", home.join("copied").display()),
+    );
+
+    let path = format!(
+        "{}:{}",
+        bin.display(),
+        std::env::var("PATH").unwrap_or_default()
+    );
+    let mut command = Command::new(env!("CARGO_BIN_EXE_rustoji"));
+    command
+        .arg("fuzzel")
+        .arg("--quiet")
+        .arg("--data-dir")
+        .arg(home.join("data"))
+        .arg("--png-dir")
+        .arg(home.join("png"))
+        .arg("--copy-cmd")
+        .arg(bin.join("copy"))
+        .env("HOME", home)
+        .env("PATH", path)
+        .env("WAYLAND_DISPLAY", "wayland-test")
+        .env_remove("RUSTOJI_PICKER")
+        .env_remove("RUSTOJI_COPY_MODE");
+    command
+}
+
+#[test]
+fn picked_emoji_is_copied_and_recorded() {
+    let home = temp_home("pick");
+    fs::create_dir_all(home.join("data")).unwrap();
+    fs::write(
+        home.join("data/emojis.json"),
+        r#"{"grinning": "😀", "thumbs up": "👍"}"#,
+    )
+    .unwrap();
+
+    let status = rustoji(&home, "👍\tthumbs up").status().unwrap();
+    assert!(status.success());
+
+    let picker_in = fs::read_to_string(home.join("picker_in")).unwrap();
+    assert!(
+        picker_in.lines().any(|line| line == "😀\tgrinning"),
+        "{picker_in:?}"
+    );
+    assert!(
+        picker_in.lines().any(|line| line == "👍\tthumbs up"),
+        "{picker_in:?}"
+    );
+    assert_eq!(fs::read_to_string(home.join("copied")).unwrap(), "👍");
+    let history = fs::read_to_string(home.join("data/history.json")).unwrap();
+    let history: serde_json::Value = serde_json::from_str(&history).unwrap();
+    assert_eq!(history["thumbs up"]["count"], 1);
+
+    // the pick comes back first next time
+    let status = rustoji(&home, "").status().unwrap();
+    assert!(status.success());
+    let picker_in = fs::read_to_string(home.join("picker_in")).unwrap();
+    assert_eq!(picker_in.lines().next(), Some("👍\tthumbs up"));
+    fs::remove_dir_all(&home).unwrap();
+}